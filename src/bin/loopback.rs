@@ -0,0 +1,122 @@
+extern crate retris;
+
+use std::thread;
+use std::time::Duration;
+
+use retris::playfield::{Action, Input};
+use retris::rng::Rng;
+use retris::rollback::{RollbackSession, UdpLink, Versus};
+use retris::rules::Rules;
+
+// NOTE: Plays a rollback versus game between two sessions over
+// UDP on loopback, with a bot for each player, and checks both ended up
+// with the same boards as playing the same inputs without a network:
+//
+//   loopback [delay ms] [loss %] [frames]
+
+const PORTS: [u16; 2] = [7301, 7302];
+
+const SEED: u64 = 42;
+
+// NOTE: Random presses and releases, the same for the same player.
+fn bot_inputs(player: usize, frames: u32) -> Vec<Vec<Input>> {
+    let actions = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::RotateClockwise,
+        Action::RotateCounterClockwise,
+        Action::Hold,
+    ];
+
+    let mut rng = Rng::new(SEED + player as u64);
+    (0..frames).map(|_| {
+        let mut inputs = vec![];
+        match rng.gen_range(20) {
+            0 => inputs.push(Input::Pressed(actions[rng.gen_range(actions.len())])),
+            1 | 2 => inputs.push(Input::Released(actions[rng.gen_range(actions.len())])),
+            _ => {}
+        }
+        inputs
+    }).collect()
+}
+
+fn play(player: usize, delay: u64, loss: u32, inputs: Vec<Vec<Input>>) -> Result<(u64, u32, u32, u32), String> {
+    let local = format!("127.0.0.1:{}", PORTS[player]);
+    let peer = format!("127.0.0.1:{}", PORTS[1 - player]);
+    let mut link = UdpLink::new(&local, &peer)?;
+    link.simulate(Duration::from_millis(delay), loss, SEED + player as u64);
+
+    let frames = inputs.len() as u32;
+    let mut session = RollbackSession::new(&Rules::new(), SEED, player, link);
+    let mut inputs = inputs.into_iter();
+    let mut stalls = 0;
+
+    // NOTE: Run at about 60 FPS until every remote input is known.
+    while session.confirmed_frame() < frames {
+        session.poll();
+        if session.frame() < frames {
+            if session.can_advance() {
+                session.advance(inputs.next().unwrap());
+            } else {
+                stalls += 1;
+            }
+        }
+
+        thread::sleep(Duration::from_millis(16));
+        if stalls > 60 * 60 {
+            return Err("the remote stopped sending".to_string());
+        }
+    }
+
+    // NOTE: The other side may still need our last inputs.
+    for _ in 0..60 {
+        session.poll();
+        thread::sleep(Duration::from_millis(16));
+    }
+
+    Ok((session.versus().checksum(), session.rollbacks(), stalls, session.bad_packets()))
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let delay = args.next().map_or(100, |arg| arg.parse::<u64>().expect("delay must be a number"));
+    let loss = args.next().map_or(10, |arg| arg.parse::<u32>().expect("loss must be a number"));
+    let frames = args.next().map_or(1200, |arg| arg.parse::<u32>().expect("frames must be a number"));
+
+    let inputs = [bot_inputs(0, frames), bot_inputs(1, frames)];
+
+    // NOTE: The boards both sides must end up with.
+    let mut versus = Versus::new(&Rules::new(), SEED);
+    for (input0, input1) in inputs[0].iter().zip(&inputs[1]) {
+        versus.tick([input0, input1]);
+    }
+    let expected = versus.checksum();
+
+    println!("{} frames, {} ms delay, {}% loss", frames, delay, loss);
+    let players = (0..2).map(|player| {
+        let inputs = inputs[player].clone();
+        thread::spawn(move || play(player, delay, loss, inputs))
+    }).collect::<Vec<_>>();
+
+    let mut is_ok = true;
+    for (player, handle) in players.into_iter().enumerate() {
+        match handle.join().unwrap() {
+            Ok((checksum, rollbacks, stalls, bad_packets)) => {
+                let is_same = checksum == expected;
+                is_ok = is_ok && is_same;
+                println!("player {}: {} rollbacks, {} stalled frames, {} bad packets, {}",
+                         player + 1, rollbacks, stalls, bad_packets, if is_same { "in sync" } else { "DESYNC" });
+            }
+            Err(e) => {
+                is_ok = false;
+                println!("player {}: {}", player + 1, e);
+            }
+        }
+    }
+
+    if !is_ok {
+        std::process::exit(1);
+    }
+}
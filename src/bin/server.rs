@@ -0,0 +1,314 @@
+extern crate rand;
+extern crate retris;
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use retris::mode::{Mode, ModeOptions};
+use retris::net::{Connection, MatchResult, Message, Mirror, DEFAULT_PORT, HANDSHAKE_TIMEOUT};
+use retris::playfield::Playfield;
+
+// NOTE: How long the results of a game get to reach the players before
+// the server hangs up on them.
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// NOTE: A match server for network versus, no window needed.
+// Players `retris --join` it like a host, the first two waiting are put in
+// a room and get the same seed. The server plays both playfields from their
+// messages with the same rules as the game, so the garbage each player
+// sends is worked out here and not taken from the client. The playfield
+// that tops out on the earlier frame loses, no matter whose messages arrive
+// first, and both topping out on the same frame is a draw. A player that
+// leaves before that is decided loses:
+//
+//   server [port]
+
+struct Player {
+    connection: Connection,
+    address: SocketAddr,
+    playfield: Playfield,
+    mirror: Mirror,
+    // NOTE: The frame the playfield topped out on.
+    lost_frame: Option<u32>,
+    // NOTE: Why the player left.
+    error: Option<String>,
+}
+
+impl Player {
+    fn is_out(&self) -> bool {
+        self.lost_frame.is_some() || self.error.is_some()
+    }
+
+    fn send(&mut self, message: &Message) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.connection.send(message) {
+            self.error = Some(e);
+        }
+    }
+
+    // NOTE: A player that can't keep up with the messages is dropped, the
+    // server never waits on one.
+    fn flush(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.connection.flush() {
+            self.error = Some(e);
+        }
+    }
+}
+
+struct Room {
+    id: u32,
+    players: [Player; 2],
+}
+
+impl Room {
+    fn new(id: u32, first: (Connection, SocketAddr), second: (Connection, SocketAddr)) -> Room {
+        let seed = rand::random::<u64>();
+        let rules = Mode::Versus.rules(&ModeOptions::new());
+        println!("room {}: {} vs {}, seed {}", id, first.1, second.1, seed);
+
+        let player = |(connection, address): (Connection, SocketAddr)| {
+            let mut player = Player {
+                connection: connection,
+                address: address,
+                playfield: Playfield::new(&rules, seed),
+                mirror: Mirror::new(),
+                lost_frame: None,
+                error: None,
+            };
+            player.send(&Message::Start {seed: seed});
+            player
+        };
+        Room {
+            id: id,
+            players: [player(first), player(second)],
+        }
+    }
+
+    // NOTE: Relays the messages of one player to the other, and plays the
+    // playfield of the player as far as the messages go.
+    fn update_player(&mut self, index: usize) {
+        let (left, right) = self.players.split_at_mut(1);
+        let (player, opponent) = if index == 0 {
+            (&mut left[0], &mut right[0])
+        } else {
+            (&mut right[0], &mut left[0])
+        };
+
+        if player.error.is_some() {
+            return;
+        }
+
+        let messages = match player.connection.receive() {
+            Ok(messages) => messages,
+            Err(e) => {
+                player.error = Some(e);
+                return;
+            }
+        };
+
+        for message in messages {
+            match message {
+                Message::Input {..} | Message::Garbage {..} | Message::Frame {..} => {
+                    if let Err(e) = player.mirror.handle(message) {
+                        player.error = Some(e);
+                        return;
+                    }
+                    opponent.send(&message);
+                }
+                // NOTE: The server works these out itself.
+                Message::Attack {..} | Message::Lost {..} => {}
+                _ => {
+                    player.error = Some(format!("unexpected `{}`", message.format()));
+                    return;
+                }
+            }
+        }
+
+        // NOTE: One frame at a time to catch the frame it tops out on.
+        let mut rows = 0;
+        while let Some(sent) = player.mirror.advance_frame(&mut player.playfield) {
+            rows += sent;
+            if player.lost_frame.is_none() && player.playfield.is_lost() {
+                player.lost_frame = Some(player.playfield.frame());
+            }
+        }
+        if rows > 0 && !opponent.is_out() {
+            opponent.send(&Message::Attack {rows: rows});
+        }
+    }
+
+    // NOTE: The result of the first player once it is decided. A top out
+    // only counts once the other playfield got to that frame without
+    // topping out itself, the messages of the two players arrive in any
+    // order.
+    fn result(&self) -> Option<MatchResult> {
+        let (first, second) = (&self.players[0], &self.players[1]);
+        let survived = |player: &Player, frame: u32| player.lost_frame.is_none() && player.playfield.frame() >= frame;
+
+        match (first.lost_frame, second.lost_frame) {
+            (Some(a), Some(b)) if a < b => return Some(MatchResult::Lose),
+            (Some(a), Some(b)) if a > b => return Some(MatchResult::Win),
+            (Some(_), Some(_)) => return Some(MatchResult::Draw),
+            (Some(frame), None) if survived(second, frame) => return Some(MatchResult::Lose),
+            (None, Some(frame)) if survived(first, frame) => return Some(MatchResult::Win),
+            _ => {}
+        }
+
+        match (first.error.is_some(), second.error.is_some()) {
+            (true, true) => Some(MatchResult::Draw),
+            (true, false) => Some(MatchResult::Lose),
+            (false, true) => Some(MatchResult::Win),
+            (false, false) => None,
+        }
+    }
+
+    fn describe(player: &Player) -> String {
+        match (player.lost_frame, player.error.as_ref()) {
+            (Some(frame), _) => format!("{} topped out at frame {}", player.address, frame),
+            (None, Some(e)) => format!("{} left at frame {}, {}", player.address, player.playfield.frame(), e),
+            (None, None) => format!("{} at frame {}", player.address, player.playfield.frame()),
+        }
+    }
+
+    // NOTE: Returns true once the game is decided and the results are sent.
+    fn update(&mut self) -> bool {
+        self.update_player(0);
+        self.update_player(1);
+        self.players[0].flush();
+        self.players[1].flush();
+
+        let result = match self.result() {
+            Some(result) => result,
+            None => return false,
+        };
+
+        let (first, second) = (&self.players[0], &self.players[1]);
+        let summary = match result {
+            MatchResult::Win => format!("{} beat {}", first.address, second.address),
+            MatchResult::Lose => format!("{} beat {}", second.address, first.address),
+            MatchResult::Draw => format!("{} drew with {}", first.address, second.address),
+        };
+        println!("room {}: {}, {}, {}", self.id, summary, Room::describe(first), Room::describe(second));
+        self.players[0].send(&Message::Result {result: result});
+        self.players[1].send(&Message::Result {result: result.opposite()});
+        true
+    }
+}
+
+fn main() {
+    let port = std::env::args().nth(1).map_or(DEFAULT_PORT, |port| port.parse::<u16>().expect("port must be a number"));
+
+    let listener = TcpListener::bind(("0.0.0.0", port)).unwrap_or_else(|e| panic!("port {}: {}", port, e));
+    listener.set_nonblocking(true).expect("failed to set the listener non-blocking");
+    println!("listening on port {}", port);
+
+    // NOTE: Connected but no hello yet, and since when.
+    let mut pending: Vec<(Connection, SocketAddr, Instant)> = vec![];
+    let mut waiting: Vec<(Connection, SocketAddr)> = vec![];
+    let mut rooms: Vec<Room> = vec![];
+    // NOTE: Players of a finished game, kept until they hang up. Closing
+    // with their messages unread would reset the connection and could lose
+    // the results on the way.
+    let mut leaving: Vec<(Connection, Instant)> = vec![];
+    let mut next_room_id = 1;
+
+    loop {
+        loop {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    match Connection::accept_nonblocking(stream) {
+                        Ok(connection) => pending.push((connection, address, Instant::now())),
+                        Err(e) => println!("{}: {}", address, e),
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    println!("accept: {}", e);
+                    break;
+                }
+            }
+        }
+
+        // NOTE: The handshakes finish over several loops, a player that
+        // is slow to say hello doesn't hold up the rooms.
+        let mut i = 0;
+        while i < pending.len() {
+            let result = match pending[i].0.flush().and_then(|_| pending[i].0.receive()) {
+                Ok(messages) => {
+                    match messages.first() {
+                        Some(&message) => Connection::check_hello(message).map(|_| true),
+                        None if pending[i].2.elapsed() > HANDSHAKE_TIMEOUT => Err("no hello in time".to_string()),
+                        None => Ok(false),
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(false) => i += 1,
+                Ok(true) => {
+                    let (connection, address, _) = pending.remove(i);
+                    println!("{} joined", address);
+                    waiting.push((connection, address));
+                }
+                Err(e) => {
+                    println!("{}: {}", pending[i].1, e);
+                    pending.remove(i);
+                }
+            }
+        }
+
+        // NOTE: Nothing is sent before the start, this only notices the
+        // players who left while waiting.
+        let mut i = 0;
+        while i < waiting.len() {
+            if let Err(e) = waiting[i].0.flush().and_then(|_| waiting[i].0.receive()) {
+                println!("{} left, {}", waiting[i].1, e);
+                waiting.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        while waiting.len() >= 2 {
+            let first = waiting.remove(0);
+            let second = waiting.remove(0);
+            rooms.push(Room::new(next_room_id, first, second));
+            next_room_id += 1;
+        }
+
+        let mut finished = vec![];
+        for (i, room) in rooms.iter_mut().enumerate() {
+            if room.update() {
+                finished.push(i);
+            }
+        }
+        for i in finished.into_iter().rev() {
+            let Room {players: [first, second], ..} = rooms.remove(i);
+            for player in [first, second] {
+                if player.error.is_none() {
+                    leaving.push((player.connection, Instant::now()));
+                }
+            }
+        }
+
+        let mut i = 0;
+        while i < leaving.len() {
+            let is_gone = leaving[i].0.flush().and_then(|_| leaving[i].0.receive()).is_err();
+            if is_gone || leaving[i].1.elapsed() > LEAVE_TIMEOUT {
+                leaving.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,76 @@
+extern crate retris;
+
+use std::thread;
+use std::time::Duration;
+
+use retris::spectate::{shape_name, Snapshot, SpectatorClient, DEFAULT_SPECTATE_PORT};
+
+// NOTE: Watches a game streamed with `retris --spectate <port>` in
+// the terminal, and shows what a spectator gets to draw with:
+//
+//   watch [host:port]
+
+fn shapes(shapes: &[usize]) -> String {
+    shapes.iter().map(|&shape| shape_name(shape)).collect::<Vec<_>>().join(" ")
+}
+
+// NOTE: The board with the ghost as `+` and the active piece as `@`.
+fn draw(snapshot: &Snapshot) -> Vec<String> {
+    let mut rows = snapshot.rows.iter().map(|row| row.chars().collect::<Vec<_>>()).collect::<Vec<_>>();
+    let pieces = [(&snapshot.ghost, '+'), (&snapshot.active, '@')];
+    for &(piece, c) in &pieces {
+        if let Some(ref piece) = *piece {
+            for &(x, y) in &piece.cells {
+                let row = snapshot.height as i32 - 1 - y;
+                if x >= 0 && x < snapshot.width as i32 && row >= 0 && row < snapshot.height as i32 {
+                    rows[row as usize][x as usize] = c;
+                }
+            }
+        }
+    }
+
+    let mut lines = vec![
+        format!("player {}, {}", snapshot.player + 1, snapshot.state),
+        format!("hold {}  next {}", snapshot.hold.map_or("-", shape_name), shapes(&snapshot.next)),
+    ];
+    lines.extend(rows.into_iter().map(|row| format!("|{}|", row.into_iter().collect::<String>())));
+    lines.push(format!("score {}  lines {}  level {}", snapshot.score, snapshot.lines, snapshot.level));
+    lines.push(format!("time {}  pieces {}  incoming {}", snapshot.time, snapshot.pieces, snapshot.incoming_garbage));
+    lines
+}
+
+fn main() {
+    let address = std::env::args().nth(1).unwrap_or_else(|| format!("127.0.0.1:{}", DEFAULT_SPECTATE_PORT));
+    let mut client = SpectatorClient::connect(&address).unwrap_or_else(|e| panic!("failed to connect, {}", e));
+
+    // NOTE: The latest snapshot of each player.
+    let mut snapshots: Vec<Snapshot> = vec![];
+    loop {
+        let received = client.receive().unwrap_or_else(|e| panic!("{}", e));
+        if !received.is_empty() {
+            for snapshot in received {
+                if snapshot.player >= snapshots.len() {
+                    snapshots.push(snapshot);
+                } else {
+                    let player = snapshot.player;
+                    snapshots[player] = snapshot;
+                }
+            }
+
+            // NOTE: The boards side by side.
+            let boards = snapshots.iter().map(draw).collect::<Vec<_>>();
+            let height = boards.iter().map(|lines| lines.len()).max().unwrap_or(0);
+            let mut screen = "\x1b[H\x1b[2J".to_string();
+            for i in 0..height {
+                let line = boards.iter()
+                                 .map(|lines| format!("{:<32}", lines.get(i).map_or("", |line| line.as_str())))
+                                 .collect::<String>();
+                screen.push_str(line.trim_end());
+                screen.push('\n');
+            }
+            print!("{}", screen);
+        }
+
+        thread::sleep(Duration::from_millis(16));
+    }
+}
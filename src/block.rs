@@ -1,8 +1,7 @@
 use std::collections::VecDeque;
 
-use rand;
-
-use hammer::prelude::*;
+use randomizer::Randomizer;
+use rng::Rng;
 
 const CYAN: i32 = 6;
 const YELLOW: i32 = 0;
@@ -12,12 +11,42 @@ const RED: i32 = 2;
 const BLUE: i32 = 5;
 const ORANGE: i32 = 1;
 
+// NOTE: Shapes are indices into `BlockTemplate`'s templates.
+pub const SHAPE_I: usize = 0;
+pub const SHAPE_O: usize = 1;
+pub const SHAPE_T: usize = 2;
+pub const SHAPE_S: usize = 3;
+pub const SHAPE_Z: usize = 4;
+pub const SHAPE_J: usize = 5;
+pub const SHAPE_L: usize = 6;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
 #[derive(Copy, Clone)]
 pub struct Cell {
     pub index: i32,
-    pub color: RGBA,
+    pub color: Color,
 }
 
+// NOTE: Garbage is not a piece, it has no image.
+pub const GARBAGE_CELL: Cell = Cell {
+    index: -1,
+    color: Color {r: 0.5, g: 0.5, b: 0.5, a: 1.0},
+};
+
+impl Cell {
+    pub fn is_garbage(&self) -> bool {
+        self.index == GARBAGE_CELL.index
+    }
+}
+
+#[macro_export]
 macro_rules! block_iter {
     ($block:expr) => {
         $block.data
@@ -30,6 +59,7 @@ macro_rules! block_iter {
     }
 }
 
+#[derive(Clone)]
 pub struct Block {
     pub width: usize,
     pub height: usize,
@@ -146,7 +176,52 @@ impl Block {
                     self.set(col, row - 1, cell);
                 }
             }
+
+            let top = self.height - 1;
+            for col in 0..self.width {
+                self.set(col, top, None);
+            }
+        }
+    }
+
+    // NOTE: Pushes every cell up by one row for each hole, then fills the
+    // new rows at the bottom with `cell` except at the hole, `holes[0]` is
+    // the bottom row. Returns true if cells were pushed out of the top.
+    pub fn insert_garbage_rows(&mut self, holes: &[usize], cell: Cell) -> bool {
+        let count = holes.len().min(self.height);
+
+        let mut is_overflow = false;
+        for row in self.height - count..self.height {
+            for col in 0..self.width {
+                is_overflow |= self.get(col, row).is_some();
+            }
+        }
+
+        for row in (count..self.height).rev() {
+            for col in 0..self.width {
+                let cell = self.get(col, row - count).cloned();
+                self.set(col, row, cell);
+            }
+        }
+
+        for (row, &hole) in holes.iter().take(count).enumerate() {
+            for col in 0..self.width {
+                if col == hole {
+                    self.set(col, row, None);
+                } else {
+                    self.set_with_cell(col, row, cell);
+                }
+            }
         }
+
+        is_overflow
+    }
+
+    // NOTE: How many rows still have garbage in them.
+    pub fn garbage_rows(&self) -> usize {
+        (0..self.height).filter(|&row| {
+            (0..self.width).filter_map(|col| self.get(col, row)).any(|cell| cell.is_garbage())
+        }).count()
     }
 
     pub fn get_ghost_block_pos(&self, x: i32, mut y: i32, block: &Block) -> (i32, i32) {
@@ -159,6 +234,12 @@ impl Block {
     }
 }
 
+// NOTE: SRS has no 180 rotation, these are the kicks most games use.
+pub const ROTATE_180_WALL_KICK_TABLE: [(i32, i32); 6] = [
+    (0, 0), (0, 1), (1, 0), (-1, 0), (1, 1), (-1, 1),
+];
+
+#[derive(Clone)]
 pub struct BlockTemplate {
     templates: [[Block; 4]; 7],
     wall_kick_table: [[[(i32, i32); 5]; 8]; 2],
@@ -167,31 +248,31 @@ pub struct BlockTemplate {
 impl BlockTemplate {
     pub fn new() -> BlockTemplate {
          let cyan = Cell {
-             color: RGBA {r: 0.0, g: 240.0 / 255.0, b: 241.0 / 255.0, a: 1.0},
+             color: Color {r: 0.0, g: 240.0 / 255.0, b: 241.0 / 255.0, a: 1.0},
              index: CYAN,
          };
          let yellow = Cell {
-             color: RGBA {r: 240.0 / 255.0, g: 242.0 / 255.0, b: 0.0, a: 1.0},
+             color: Color {r: 240.0 / 255.0, g: 242.0 / 255.0, b: 0.0, a: 1.0},
              index: YELLOW,
          };
          let purple = Cell {
-             color: RGBA {r: 161.0 / 255.0, g: 0.0, b: 244.0 / 255.0, a: 1.0},
+             color: Color {r: 161.0 / 255.0, g: 0.0, b: 244.0 / 255.0, a: 1.0},
              index: PURPLE,
          };
          let green = Cell {
-             color: RGBA {r: 0.0, g: 242.0 / 255.0, b: 0.0, a: 1.0},
+             color: Color {r: 0.0, g: 242.0 / 255.0, b: 0.0, a: 1.0},
              index: GREEN,
          };
          let red = Cell {
-             color: RGBA {r: 243.0 / 255.0, g: 0.0, b: 0.0, a: 1.0},
+             color: Color {r: 243.0 / 255.0, g: 0.0, b: 0.0, a: 1.0},
              index: RED,
          };
          let blue = Cell {
-             color: RGBA {r: 0.0, g: 0.0, b: 244.0 / 255.0, a: 1.0},
+             color: Color {r: 0.0, g: 0.0, b: 244.0 / 255.0, a: 1.0},
              index: BLUE,
          };
          let orange = Cell {
-             color: RGBA {r: 242.0 / 255.0, g: 161.0 / 255.0, b: 0.0, a: 1.0},
+             color: Color {r: 242.0 / 255.0, g: 161.0 / 255.0, b: 0.0, a: 1.0},
              index: ORANGE,
          };
 
@@ -423,6 +504,10 @@ impl BlockTemplate {
         }
     }
 
+    pub fn shape_count(&self) -> usize {
+        self.templates.len()
+    }
+
     pub fn block(&self, template: &BlockTemplateRef) -> &Block {
         &self.templates[template.shape][template.order]
     }
@@ -463,32 +548,52 @@ impl BlockTemplate {
 }
 
 pub struct BlockTemplateGenerator {
+    randomizer: Box<dyn Randomizer>,
     next_templates: VecDeque<BlockTemplateRef>,
 }
 
-impl BlockTemplateGenerator {
-    pub fn new(block_template: &BlockTemplate) -> BlockTemplateGenerator {
+// NOTE: Cloned for the playfield snapshots of rollback.
+impl Clone for BlockTemplateGenerator {
+    fn clone(&self) -> BlockTemplateGenerator {
         BlockTemplateGenerator {
-            next_templates: vec![
-                BlockTemplateGenerator::generate_raw(block_template),
-                BlockTemplateGenerator::generate_raw(block_template),
-                BlockTemplateGenerator::generate_raw(block_template),
-            ].into_iter().collect(),
+            randomizer: self.randomizer.clone_box(),
+            next_templates: self.next_templates.clone(),
+        }
+    }
+}
+
+impl BlockTemplateGenerator {
+    pub fn new(block_template: &BlockTemplate, randomizer: Box<dyn Randomizer>, rng: &mut Rng) -> BlockTemplateGenerator {
+        let mut generator = BlockTemplateGenerator {
+            randomizer: randomizer,
+            next_templates: VecDeque::new(),
+        };
+
+        for _ in 0..3 {
+            let template = generator.generate_raw(block_template, rng);
+            generator.next_templates.push_back(template);
         }
+
+        generator
     }
 
-    pub fn generate(&mut self, block_template: &BlockTemplate) -> BlockTemplateRef {
-        let new_template = BlockTemplateGenerator::generate_raw(block_template);
+    pub fn generate(&mut self, block_template: &BlockTemplate, rng: &mut Rng) -> BlockTemplateRef {
+        let new_template = self.generate_raw(block_template, rng);
         self.next_templates.push_back(new_template);
-        self.next_templates.pop_front().unwrap()
+        let template = self.next_templates.pop_front().unwrap();
+        // NOTE: Keep the queue in one slice so `next_templates` always
+        // sees the whole preview.
+        self.next_templates.make_contiguous();
+        template
     }
 
     pub fn next_templates(&self) -> &[BlockTemplateRef] {
         self.next_templates.as_slices().0
     }
 
-    fn generate_raw(block_template: &BlockTemplate) -> BlockTemplateRef {
-        let shape = rand::random::<usize>() % block_template.templates.len();
+    fn generate_raw(&mut self, block_template: &BlockTemplate, rng: &mut Rng) -> BlockTemplateRef {
+        let shape = self.randomizer.next_shape(rng);
+        assert!(shape < block_template.templates.len());
         let order = 0;
         let order_max = block_template.templates[shape].len();
         BlockTemplateRef {
@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+
+use score::{LineClear, TSpin};
+use timer::Timer;
+
+// NOTE: Lines added for each combo, the first clear of a chain is combo 0,
+// longer combos send as much as the last entry.
+const COMBO_ATTACK: [u32; 13] = [0, 0, 1, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+pub const BACK_TO_BACK_ATTACK: u32 = 1;
+pub const PERFECT_CLEAR_ATTACK: u32 = 10;
+
+// NOTE: Guideline attack table, see https://tetris.wiki/Garbage. `combo` is
+// the state after the clear, as kept by `Score`. `is_back_to_back` is true
+// when this clear continues a back-to-back chain, a difficult clear while
+// `Score` still had the chain from before it.
+pub fn attack(clear: &LineClear, combo: i32, is_back_to_back: bool, is_perfect_clear: bool) -> u32 {
+    if clear.lines == 0 {
+        return 0;
+    }
+
+    let mut lines = match (clear.t_spin, clear.lines) {
+        (TSpin::None, 1) => 0,
+        (TSpin::None, 2) => 1,
+        (TSpin::None, 3) => 2,
+        (TSpin::None, _) => 4,
+
+        (TSpin::Mini, 1) => 0,
+        (TSpin::Mini, _) => 1,
+
+        (TSpin::Full, lines) => 2 * lines.min(3) as u32,
+    };
+
+    if is_back_to_back {
+        lines += BACK_TO_BACK_ATTACK;
+    }
+    if combo > 0 {
+        lines += COMBO_ATTACK[(combo as usize).min(COMBO_ATTACK.len() - 1)];
+    }
+    if is_perfect_clear {
+        lines += PERFECT_CLEAR_ATTACK;
+    }
+
+    lines
+}
+
+// NOTE: Garbage from one attack, it enters the board once the delay is
+// over.
+#[derive(Copy, Clone, Debug)]
+pub struct IncomingGarbage {
+    pub rows: u32,
+    pub delay: Timer,
+}
+
+impl IncomingGarbage {
+    pub fn is_ready(&self) -> bool {
+        self.delay.is_expired()
+    }
+}
+
+// NOTE: The garbage received but not yet in the board, oldest first.
+#[derive(Clone, Debug)]
+pub struct GarbageQueue {
+    incoming: VecDeque<IncomingGarbage>,
+}
+
+impl GarbageQueue {
+    pub fn new() -> GarbageQueue {
+        GarbageQueue {
+            incoming: VecDeque::new(),
+        }
+    }
+
+    pub fn incoming(&self) -> &VecDeque<IncomingGarbage> {
+        &self.incoming
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.incoming.iter().map(|garbage| garbage.rows).sum()
+    }
+
+    pub fn push(&mut self, rows: u32, delay: u32) {
+        if rows > 0 {
+            self.incoming.push_back(IncomingGarbage {
+                rows: rows,
+                delay: Timer::new(delay),
+            });
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for garbage in &mut self.incoming {
+            garbage.delay.tick();
+        }
+    }
+
+    // NOTE: An attack cancels the oldest garbage first, returns what is left
+    // of the attack to send.
+    pub fn cancel(&mut self, mut attack: u32) -> u32 {
+        while attack > 0 {
+            match self.incoming.front_mut() {
+                Some(garbage) if garbage.rows > attack => {
+                    garbage.rows -= attack;
+                    return 0;
+                }
+                Some(garbage) => attack -= garbage.rows,
+                None => break,
+            }
+            self.incoming.pop_front();
+        }
+        attack
+    }
+
+    // NOTE: Removes the garbage whose delay is over, the rows of each
+    // attack.
+    pub fn take_ready(&mut self) -> Vec<u32> {
+        let mut ready = vec![];
+        while let Some(&garbage) = self.incoming.front() {
+            if !garbage.is_ready() {
+                break;
+            }
+            ready.push(garbage.rows);
+            self.incoming.pop_front();
+        }
+        ready
+    }
+}
+
+impl Default for GarbageQueue {
+    fn default() -> GarbageQueue {
+        GarbageQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear(lines: usize, t_spin: TSpin) -> LineClear {
+        LineClear {
+            lines: lines,
+            t_spin: t_spin,
+        }
+    }
+
+    #[test]
+    fn attack_table() {
+        let table = [
+            (TSpin::None, 0, 0), (TSpin::None, 1, 0), (TSpin::None, 2, 1), (TSpin::None, 3, 2), (TSpin::None, 4, 4),
+            (TSpin::Mini, 0, 0), (TSpin::Mini, 1, 0), (TSpin::Mini, 2, 1),
+            (TSpin::Full, 0, 0), (TSpin::Full, 1, 2), (TSpin::Full, 2, 4), (TSpin::Full, 3, 6),
+        ];
+        for &(t_spin, lines, rows) in &table {
+            assert_eq!(attack(&clear(lines, t_spin), 0, false, false), rows, "{:?} {}", t_spin, lines);
+        }
+    }
+
+    #[test]
+    fn attack_bonuses() {
+        let tetris = clear(4, TSpin::None);
+        assert_eq!(attack(&tetris, 0, true, false), 4 + BACK_TO_BACK_ATTACK);
+        assert_eq!(attack(&tetris, 0, false, true), 4 + PERFECT_CLEAR_ATTACK);
+        assert_eq!(attack(&clear(1, TSpin::None), 2, false, false), 1);
+        assert_eq!(attack(&clear(1, TSpin::None), 100, false, false), 5);
+        assert_eq!(attack(&clear(0, TSpin::None), 5, true, true), 0);
+    }
+
+    #[test]
+    fn cancel_oldest_first() {
+        let mut queue = GarbageQueue::new();
+        queue.push(2, 30);
+        queue.push(3, 30);
+
+        assert_eq!(queue.cancel(1), 0);
+        assert_eq!(queue.rows(), 4);
+        assert_eq!(queue.incoming()[0].rows, 1);
+
+        assert_eq!(queue.cancel(2), 0);
+        assert_eq!(queue.incoming().len(), 1);
+        assert_eq!(queue.incoming()[0].rows, 2);
+
+        assert_eq!(queue.cancel(5), 3);
+        assert_eq!(queue.rows(), 0);
+        assert_eq!(queue.cancel(4), 4);
+    }
+
+    #[test]
+    fn take_ready_after_delay() {
+        let mut queue = GarbageQueue::new();
+        queue.push(2, 2);
+        queue.push(0, 0);
+        queue.push(1, 3);
+        assert_eq!(queue.incoming().len(), 2);
+
+        queue.tick();
+        assert!(queue.take_ready().is_empty());
+        queue.tick();
+        assert_eq!(queue.take_ready(), vec![2]);
+        queue.tick();
+        assert_eq!(queue.take_ready(), vec![1]);
+        assert_eq!(queue.rows(), 0);
+    }
+}
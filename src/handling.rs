@@ -0,0 +1,216 @@
+// NOTE: How held movement behaves. Delays are in frames.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Handling {
+    // NOTE: Delayed auto shift, how long a direction is held before it
+    // starts to repeat.
+    pub das: u32,
+    // NOTE: Auto repeat rate, frames between repeated moves. 0 moves the
+    // block to the wall at once.
+    pub arr: u32,
+    // NOTE: How long auto repeat pauses after a spawn or a rotation, the
+    // charge is kept.
+    pub das_cut: u32,
+    // NOTE: Gravity is multiplied by this while soft drop is held. 0 drops
+    // the block to the stack at once (sonic drop).
+    pub soft_drop_factor: u32,
+}
+
+impl Handling {
+    pub fn new() -> Handling {
+        Handling {
+            das: 10,
+            arr: 2,
+            das_cut: 0,
+            soft_drop_factor: 20,
+        }
+    }
+}
+
+impl Default for Handling {
+    fn default() -> Handling {
+        Handling::new()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AutoShift {
+    handling: Handling,
+
+    is_left_held: bool,
+    is_right_held: bool,
+    // NOTE: The last pressed direction that is still held, -1, 0 or 1.
+    direction: i32,
+
+    charge: u32,
+    repeat_delay: u32,
+    cut_delay: u32,
+}
+
+impl AutoShift {
+    pub fn new(handling: Handling) -> AutoShift {
+        AutoShift {
+            handling: handling,
+
+            is_left_held: false,
+            is_right_held: false,
+            direction: 0,
+
+            charge: 0,
+            repeat_delay: 0,
+            cut_delay: 0,
+        }
+    }
+
+    pub fn direction(&self) -> i32 {
+        self.direction
+    }
+
+    fn restart(&mut self, direction: i32) {
+        self.direction = direction;
+        self.charge = 0;
+        self.repeat_delay = 0;
+    }
+
+    pub fn press(&mut self, direction: i32) {
+        if direction < 0 {
+            self.is_left_held = true;
+        } else {
+            self.is_right_held = true;
+        }
+        self.restart(direction);
+    }
+
+    pub fn release(&mut self, direction: i32) {
+        if direction < 0 {
+            self.is_left_held = false;
+        } else {
+            self.is_right_held = false;
+        }
+
+        if self.direction == direction {
+            // NOTE: Fall back to the other direction if it is still held.
+            if self.is_left_held {
+                self.restart(-1);
+            } else if self.is_right_held {
+                self.restart(1);
+            } else {
+                self.restart(0);
+            }
+        }
+    }
+
+    pub fn cut(&mut self) {
+        self.cut_delay = self.handling.das_cut;
+    }
+
+    // NOTE: Called once per frame whether there is a falling block or not,
+    // so DAS keeps charging during spawn delay and line clears. Returns how
+    // many cells to move towards `direction`, `u32::MAX` means to the wall.
+    pub fn tick(&mut self) -> u32 {
+        if self.direction == 0 {
+            return 0;
+        }
+
+        if self.charge < self.handling.das {
+            self.charge += 1;
+        }
+
+        if self.cut_delay > 0 {
+            self.cut_delay -= 1;
+            return 0;
+        }
+
+        if self.charge < self.handling.das {
+            return 0;
+        }
+
+        if self.handling.arr == 0 {
+            return u32::MAX;
+        }
+
+        if self.repeat_delay > 0 {
+            self.repeat_delay -= 1;
+            return 0;
+        }
+
+        self.repeat_delay = self.handling.arr - 1;
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handling(das: u32, arr: u32, das_cut: u32) -> Handling {
+        Handling {
+            das: das,
+            arr: arr,
+            das_cut: das_cut,
+            soft_drop_factor: 20,
+        }
+    }
+
+    fn ticks(auto_shift: &mut AutoShift, frames: usize) -> Vec<u32> {
+        (0..frames).map(|_| auto_shift.tick()).collect()
+    }
+
+    #[test]
+    fn no_shift_without_a_held_direction() {
+        let mut auto_shift = AutoShift::new(handling(10, 2, 0));
+        assert_eq!(ticks(&mut auto_shift, 20), vec![0; 20]);
+
+        auto_shift.press(1);
+        auto_shift.release(1);
+        assert_eq!(auto_shift.direction(), 0);
+        assert_eq!(ticks(&mut auto_shift, 20), vec![0; 20]);
+    }
+
+    #[test]
+    fn repeats_every_arr_frames_after_das() {
+        let mut auto_shift = AutoShift::new(handling(10, 2, 0));
+        auto_shift.press(-1);
+        assert_eq!(auto_shift.direction(), -1);
+
+        assert_eq!(ticks(&mut auto_shift, 9), vec![0; 9]);
+        assert_eq!(ticks(&mut auto_shift, 6), vec![1, 0, 1, 0, 1, 0]);
+
+        let mut auto_shift = AutoShift::new(handling(5, 3, 0));
+        auto_shift.press(1);
+        assert_eq!(ticks(&mut auto_shift, 4), vec![0; 4]);
+        assert_eq!(ticks(&mut auto_shift, 6), vec![1, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn arr_0_goes_to_the_wall() {
+        let mut auto_shift = AutoShift::new(handling(10, 0, 0));
+        auto_shift.press(1);
+
+        assert_eq!(ticks(&mut auto_shift, 9), vec![0; 9]);
+        assert_eq!(ticks(&mut auto_shift, 2), vec![u32::MAX, u32::MAX]);
+    }
+
+    #[test]
+    fn cut_pauses_and_keeps_the_charge() {
+        let mut auto_shift = AutoShift::new(handling(10, 1, 4));
+        auto_shift.press(1);
+        assert_eq!(ticks(&mut auto_shift, 10)[9], 1);
+
+        auto_shift.cut();
+        assert_eq!(ticks(&mut auto_shift, 4), vec![0; 4]);
+        assert_eq!(ticks(&mut auto_shift, 2), vec![1, 1]);
+    }
+
+    #[test]
+    fn release_falls_back_to_the_held_direction() {
+        let mut auto_shift = AutoShift::new(handling(3, 1, 0));
+        auto_shift.press(-1);
+        auto_shift.press(1);
+        assert_eq!(auto_shift.direction(), 1);
+
+        auto_shift.release(1);
+        assert_eq!(auto_shift.direction(), -1);
+        // NOTE: The charge starts over.
+        assert_eq!(ticks(&mut auto_shift, 4), vec![0, 0, 1, 1]);
+    }
+}
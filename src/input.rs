@@ -0,0 +1,650 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::Read;
+
+use hammer::prelude::*;
+use sdl2::controller::{Axis, Button};
+use sdl2::sys;
+
+use retris::handling::Handling;
+use retris::playfield::Action;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MenuAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InputAction {
+    Playfield(Action),
+    Pause,
+    Menu(MenuAction),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    Pressed(InputAction),
+    Released(InputAction),
+}
+
+pub fn action_from_name(name: &str) -> Option<InputAction> {
+    match name {
+        "pause" => Some(InputAction::Pause),
+        "menu_up" => Some(InputAction::Menu(MenuAction::Up)),
+        "menu_down" => Some(InputAction::Menu(MenuAction::Down)),
+        "menu_left" => Some(InputAction::Menu(MenuAction::Left)),
+        "menu_right" => Some(InputAction::Menu(MenuAction::Right)),
+        "menu_confirm" => Some(InputAction::Menu(MenuAction::Confirm)),
+        "menu_back" => Some(InputAction::Menu(MenuAction::Back)),
+        _ => Action::from_name(name).map(InputAction::Playfield),
+    }
+}
+
+fn bind_action<K: ::std::hash::Hash + Eq>(bindings: &mut HashMap<K, Vec<InputAction>>, input: K, action: InputAction) {
+    let actions = bindings.entry(input).or_default();
+    if !actions.contains(&action) {
+        actions.push(action);
+    }
+}
+
+// NOTE: Maps keys to actions, any number of keys can share an action and
+// a key can trigger several actions (`Up` rotates in game and moves up in
+// menus).
+#[derive(Clone)]
+pub struct KeyBindings {
+    bindings: HashMap<Keycode, Vec<InputAction>>,
+}
+
+impl KeyBindings {
+    pub fn new() -> KeyBindings {
+        let mut bindings = KeyBindings::empty();
+
+        bindings.bind(Keycode::Left, InputAction::Playfield(Action::MoveLeft));
+        bindings.bind(Keycode::Right, InputAction::Playfield(Action::MoveRight));
+        bindings.bind(Keycode::Down, InputAction::Playfield(Action::SoftDrop));
+        bindings.bind(Keycode::Space, InputAction::Playfield(Action::HardDrop));
+        bindings.bind(Keycode::Up, InputAction::Playfield(Action::RotateClockwise));
+        bindings.bind(Keycode::X, InputAction::Playfield(Action::RotateClockwise));
+        bindings.bind(Keycode::Z, InputAction::Playfield(Action::RotateCounterClockwise));
+        bindings.bind(Keycode::A, InputAction::Playfield(Action::Rotate180));
+        bindings.bind(Keycode::C, InputAction::Playfield(Action::Hold));
+        bindings.bind(Keycode::P, InputAction::Pause);
+        bindings.bind(Keycode::Escape, InputAction::Pause);
+        bindings.bind(Keycode::Up, InputAction::Menu(MenuAction::Up));
+        bindings.bind(Keycode::Down, InputAction::Menu(MenuAction::Down));
+        bindings.bind(Keycode::Left, InputAction::Menu(MenuAction::Left));
+        bindings.bind(Keycode::Right, InputAction::Menu(MenuAction::Right));
+        bindings.bind(Keycode::Return, InputAction::Menu(MenuAction::Confirm));
+        bindings.bind(Keycode::Space, InputAction::Menu(MenuAction::Confirm));
+        bindings.bind(Keycode::Escape, InputAction::Menu(MenuAction::Back));
+        bindings.bind(Keycode::Backspace, InputAction::Menu(MenuAction::Back));
+
+        bindings
+    }
+
+    // NOTE: Two players share the keyboard in versus, player 1 on the left
+    // and player 2 on the right. Only the playfield actions, the menus use
+    // the `[keyboard]` bindings.
+    pub fn versus(player: usize) -> KeyBindings {
+        let mut bindings = KeyBindings::empty();
+
+        let keys = if player == 0 {
+            [Keycode::A, Keycode::D, Keycode::S, Keycode::W, Keycode::E, Keycode::Q, Keycode::LShift]
+        } else {
+            [Keycode::J, Keycode::L, Keycode::K, Keycode::I, Keycode::O, Keycode::U, Keycode::H]
+        };
+        let actions = [
+            Action::MoveLeft,
+            Action::MoveRight,
+            Action::SoftDrop,
+            Action::HardDrop,
+            Action::RotateClockwise,
+            Action::RotateCounterClockwise,
+            Action::Hold,
+        ];
+        for (&keycode, &action) in keys.iter().zip(actions.iter()) {
+            bindings.bind(keycode, InputAction::Playfield(action));
+        }
+
+        bindings
+    }
+
+    pub fn empty() -> KeyBindings {
+        KeyBindings {
+            bindings: HashMap::new(),
+        }
+    }
+
+    pub fn bind(&mut self, keycode: Keycode, action: InputAction) {
+        bind_action(&mut self.bindings, keycode, action);
+    }
+
+    fn bind_names(&mut self, names: &[&str], action: InputAction) -> Result<(), String> {
+        for &name in names {
+            match Keycode::from_name(name) {
+                Some(keycode) => self.bind(keycode, action),
+                None => return Err(format!("unknown key `{}`", name)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn translate(&self, event: &Event) -> Vec<InputEvent> {
+        let (keycode, is_pressed) = match *event {
+            // NOTE: Auto repeat is done by the playfield, ignore the key
+            // repeat from the OS.
+            Event::KeyDown {keycode: Some(keycode), repeat: false, ..} => (keycode, true),
+            Event::KeyUp {keycode: Some(keycode), ..} => (keycode, false),
+            _ => return vec![],
+        };
+
+        match self.bindings.get(&keycode) {
+            Some(actions) => {
+                actions.iter()
+                       .map(|&action| if is_pressed {
+                           InputEvent::Pressed(action)
+                       } else {
+                           InputEvent::Released(action)
+                       })
+                       .collect()
+            }
+            None => vec![],
+        }
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings::new()
+    }
+}
+
+// NOTE: Maps controller buttons and stick directions to actions. A stick
+// direction counts as pressed once it leaves the deadzone.
+#[derive(Clone)]
+pub struct ControllerBindings {
+    buttons: HashMap<Button, Vec<InputAction>>,
+    axes: HashMap<(Axis, i32), Vec<InputAction>>,
+    deadzone: i16,
+}
+
+impl ControllerBindings {
+    pub fn new() -> ControllerBindings {
+        let mut bindings = ControllerBindings::empty();
+
+        bindings.bind_button(Button::DPadLeft, InputAction::Playfield(Action::MoveLeft));
+        bindings.bind_button(Button::DPadRight, InputAction::Playfield(Action::MoveRight));
+        bindings.bind_button(Button::DPadDown, InputAction::Playfield(Action::SoftDrop));
+        bindings.bind_button(Button::DPadUp, InputAction::Playfield(Action::HardDrop));
+        bindings.bind_button(Button::A, InputAction::Playfield(Action::RotateCounterClockwise));
+        bindings.bind_button(Button::B, InputAction::Playfield(Action::RotateClockwise));
+        bindings.bind_button(Button::Y, InputAction::Playfield(Action::Rotate180));
+        bindings.bind_button(Button::LeftShoulder, InputAction::Playfield(Action::Hold));
+        bindings.bind_button(Button::RightShoulder, InputAction::Playfield(Action::Hold));
+        bindings.bind_button(Button::Start, InputAction::Pause);
+        bindings.bind_axis(Axis::LeftX, -1, InputAction::Playfield(Action::MoveLeft));
+        bindings.bind_axis(Axis::LeftX, 1, InputAction::Playfield(Action::MoveRight));
+        bindings.bind_axis(Axis::LeftY, 1, InputAction::Playfield(Action::SoftDrop));
+        bindings.bind_button(Button::DPadUp, InputAction::Menu(MenuAction::Up));
+        bindings.bind_button(Button::DPadDown, InputAction::Menu(MenuAction::Down));
+        bindings.bind_button(Button::DPadLeft, InputAction::Menu(MenuAction::Left));
+        bindings.bind_button(Button::DPadRight, InputAction::Menu(MenuAction::Right));
+        bindings.bind_button(Button::A, InputAction::Menu(MenuAction::Confirm));
+        bindings.bind_button(Button::Start, InputAction::Menu(MenuAction::Confirm));
+        bindings.bind_button(Button::B, InputAction::Menu(MenuAction::Back));
+        bindings.bind_button(Button::Back, InputAction::Menu(MenuAction::Back));
+        bindings.bind_axis(Axis::LeftY, -1, InputAction::Menu(MenuAction::Up));
+        bindings.bind_axis(Axis::LeftY, 1, InputAction::Menu(MenuAction::Down));
+        bindings.bind_axis(Axis::LeftX, -1, InputAction::Menu(MenuAction::Left));
+        bindings.bind_axis(Axis::LeftX, 1, InputAction::Menu(MenuAction::Right));
+
+        bindings
+    }
+
+    pub fn empty() -> ControllerBindings {
+        ControllerBindings {
+            buttons: HashMap::new(),
+            axes: HashMap::new(),
+            deadzone: 8000,
+        }
+    }
+
+    pub fn bind_button(&mut self, button: Button, action: InputAction) {
+        bind_action(&mut self.buttons, button, action);
+    }
+
+    pub fn bind_axis(&mut self, axis: Axis, direction: i32, action: InputAction) {
+        bind_action(&mut self.axes, (axis, direction), action);
+    }
+
+    // NOTE: Buttons use the SDL names (`a`, `dpleft`, `leftshoulder`...),
+    // stick directions are an axis name followed by `+` or `-` (`leftx-`).
+    fn bind_names(&mut self, names: &[&str], action: InputAction) -> Result<(), String> {
+        for &name in names {
+            if let Some(button) = Button::from_string(name) {
+                self.bind_button(button, action);
+                continue;
+            }
+
+            let (axis, direction) = if let Some(axis) = name.strip_suffix('+') {
+                (axis, 1)
+            } else if let Some(axis) = name.strip_suffix('-') {
+                (axis, -1)
+            } else {
+                return Err(format!("unknown button `{}`", name));
+            };
+
+            match Axis::from_string(axis) {
+                Some(axis) => self.bind_axis(axis, direction, action),
+                None => return Err(format!("unknown axis `{}`", name)),
+            }
+        }
+        Ok(())
+    }
+
+    fn axis_direction(&self, value: i16) -> i32 {
+        if value > self.deadzone {
+            1
+        } else if value < -self.deadzone {
+            -1
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for ControllerBindings {
+    fn default() -> ControllerBindings {
+        ControllerBindings::new()
+    }
+}
+
+// NOTE: All bindings from the config file. It has a `[keyboard]` section,
+// a `[controller]` section used by every controller, and optional
+// `[controller <name>]` sections for controllers with that SDL name. The
+// `[keyboard 1]` and `[keyboard 2]` sections replace the keys of each player
+// in versus. The `[handling]` section sets DAS, ARR and DAS cut in frames,
+// and the soft drop factor:
+//
+//   [keyboard]
+//   rotate_cw = Up, X
+//
+//   [keyboard 2]
+//   move_left = J
+//
+//   [controller]
+//   deadzone = 8000
+//   move_left = dpleft, leftx-
+//
+//   [handling]
+//   das = 8
+//
+// Lines are `action = input, input, ...` and `#` starts a comment.
+#[derive(Clone)]
+pub struct InputConfig {
+    pub handling: Handling,
+    pub keyboard: KeyBindings,
+    pub versus_keyboards: Vec<KeyBindings>,
+    pub controller: ControllerBindings,
+    pub named_controllers: HashMap<String, ControllerBindings>,
+}
+
+enum Section {
+    Keyboard,
+    VersusKeyboard(usize),
+    Controller,
+    NamedController(String),
+    Handling,
+}
+
+fn parse_handling(handling: &mut Handling, name: &str, inputs: &[&str]) -> Result<(), String> {
+    let value = match inputs {
+        [value] => value.parse::<u32>().map_err(|_| format!("{} must be a number", name))?,
+        _ => return Err(format!("expected `{} = <number>`", name)),
+    };
+    match name {
+        "das" => handling.das = value,
+        "arr" => handling.arr = value,
+        "das_cut" => handling.das_cut = value,
+        "soft_drop_factor" => handling.soft_drop_factor = value,
+        _ => return Err(format!("unknown handling `{}`", name)),
+    }
+    Ok(())
+}
+
+impl InputConfig {
+    pub fn new() -> InputConfig {
+        InputConfig {
+            handling: Handling::new(),
+            keyboard: KeyBindings::new(),
+            versus_keyboards: vec![KeyBindings::versus(0), KeyBindings::versus(1)],
+            controller: ControllerBindings::new(),
+            named_controllers: HashMap::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<InputConfig, String> {
+        let mut config = InputConfig {
+            handling: Handling::new(),
+            keyboard: KeyBindings::empty(),
+            versus_keyboards: vec![KeyBindings::versus(0), KeyBindings::versus(1)],
+            controller: ControllerBindings::empty(),
+            named_controllers: HashMap::new(),
+        };
+
+        // NOTE: Lines before any section are keyboard bindings.
+        let mut section = Section::Keyboard;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].trim();
+                section = if name == "keyboard" {
+                    Section::Keyboard
+                } else if name == "keyboard 1" || name == "keyboard 2" {
+                    let player = if name == "keyboard 1" { 0 } else { 1 };
+                    config.versus_keyboards[player] = KeyBindings::empty();
+                    Section::VersusKeyboard(player)
+                } else if name == "controller" {
+                    Section::Controller
+                } else if let Some(name) = name.strip_prefix("controller ") {
+                    let name = name.trim().to_string();
+                    config.named_controllers.entry(name.clone()).or_insert_with(ControllerBindings::empty);
+                    Section::NamedController(name)
+                } else if name == "handling" {
+                    Section::Handling
+                } else {
+                    return Err(format!("line {}: unknown section `{}`", i + 1, name));
+                };
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let inputs = match parts.next() {
+                Some(inputs) => inputs,
+                None => return Err(format!("line {}: expected `action = inputs`", i + 1)),
+            };
+            let inputs = inputs.split(',')
+                               .map(|input| input.trim())
+                               .filter(|input| !input.is_empty())
+                               .collect::<Vec<_>>();
+
+            if let Section::Handling = section {
+                parse_handling(&mut config.handling, name, &inputs).map_err(|e| format!("line {}: {}", i + 1, e))?;
+                continue;
+            }
+
+            let (keyboard, controller) = match section {
+                Section::Handling => (None, None),
+                Section::Keyboard => (Some(&mut config.keyboard), None),
+                Section::VersusKeyboard(player) => (Some(&mut config.versus_keyboards[player]), None),
+                Section::Controller => (None, Some(&mut config.controller)),
+                Section::NamedController(ref name) => (None, config.named_controllers.get_mut(name)),
+            };
+
+            let result = match controller {
+                Some(controller) => {
+                    if name == "deadzone" {
+                        match inputs.first().and_then(|value| value.parse::<i16>().ok()) {
+                            Some(deadzone) => {
+                                controller.deadzone = deadzone;
+                                Ok(())
+                            }
+                            None => Err("deadzone must be a number up to 32767".to_string()),
+                        }
+                    } else {
+                        match action_from_name(name) {
+                            Some(action) => controller.bind_names(&inputs, action),
+                            None => Err(format!("unknown action `{}`", name)),
+                        }
+                    }
+                }
+                None => {
+                    match (action_from_name(name), keyboard) {
+                        (Some(action), Some(keyboard)) => keyboard.bind_names(&inputs, action),
+                        _ => Err(format!("unknown action `{}`", name)),
+                    }
+                }
+            };
+
+            result.map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn load(path: &str) -> Result<InputConfig, String> {
+        let mut text = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+                        .map_err(|e| format!("{}: {}", path, e))?;
+        InputConfig::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // NOTE: Falls back to the default bindings if the file is missing or
+    // broken.
+    pub fn load_or_default(path: &str) -> InputConfig {
+        match InputConfig::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("using default bindings, {}", e);
+                InputConfig::new()
+            }
+        }
+    }
+
+    fn controller_bindings(&self, name: &str) -> ControllerBindings {
+        self.named_controllers.get(name).unwrap_or(&self.controller).clone()
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> InputConfig {
+        InputConfig::new()
+    }
+}
+
+struct Controller {
+    raw: *mut sys::SDL_GameController,
+    instance_id: u32,
+    bindings: ControllerBindings,
+    axis_directions: HashMap<Axis, i32>,
+}
+
+// NOTE: Opens controllers as they are plugged in and translates their
+// events. Each controller keeps the slot it got when connected, a
+// controller plugged in later takes the first free slot.
+pub struct Controllers {
+    config: InputConfig,
+    slots: Vec<Option<Controller>>,
+}
+
+impl Controllers {
+    pub fn new(config: InputConfig) -> Controllers {
+        // NOTE: hammer owns the SDL context and only starts video, the
+        // controller subsystem is started here so SDL sends the device
+        // added events for controllers that are already plugged in.
+        unsafe {
+            if sys::SDL_InitSubSystem(sys::SDL_INIT_GAMECONTROLLER) != 0 {
+                println!("failed to start the game controller subsystem");
+            }
+        }
+
+        Controllers {
+            config: config,
+            slots: vec![],
+        }
+    }
+
+    fn slot_of(&self, instance_id: u32) -> Option<usize> {
+        self.slots.iter().position(|controller| {
+            controller.as_ref().is_some_and(|controller| controller.instance_id == instance_id)
+        })
+    }
+
+    fn open(&mut self, device_index: u32) {
+        let controller = unsafe {
+            let raw = sys::SDL_GameControllerOpen(device_index as i32);
+            if raw.is_null() {
+                println!("failed to open controller {}", device_index);
+                return;
+            }
+
+            let instance_id = sys::SDL_JoystickInstanceID(sys::SDL_GameControllerGetJoystick(raw)) as u32;
+            if self.slot_of(instance_id).is_some() {
+                // NOTE: Already open, SDL hands out a new reference.
+                sys::SDL_GameControllerClose(raw);
+                return;
+            }
+
+            let name = sys::SDL_GameControllerName(raw);
+            let name = if name.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(name).to_string_lossy().into_owned()
+            };
+            println!("controller connected: {}", name);
+
+            Controller {
+                raw: raw,
+                instance_id: instance_id,
+                bindings: self.config.controller_bindings(&name),
+                axis_directions: HashMap::new(),
+            }
+        };
+
+        match self.slots.iter().position(|controller| controller.is_none()) {
+            Some(slot) => self.slots[slot] = Some(controller),
+            None => self.slots.push(Some(controller)),
+        }
+    }
+
+    // NOTE: Releases every held action of the controller so nothing keeps
+    // auto repeating after it is unplugged.
+    fn close(&mut self, slot: usize, inputs: &mut Vec<(usize, InputEvent)>) {
+        if let Some(controller) = self.slots[slot].take() {
+            for (&(axis, direction), actions) in &controller.bindings.axes {
+                if controller.axis_directions.get(&axis) == Some(&direction) {
+                    inputs.extend(actions.iter().map(|&action| (slot, InputEvent::Released(action))));
+                }
+            }
+            for actions in controller.bindings.buttons.values() {
+                inputs.extend(actions.iter().map(|&action| (slot, InputEvent::Released(action))));
+            }
+
+            unsafe {
+                sys::SDL_GameControllerClose(controller.raw);
+            }
+            println!("controller disconnected");
+        }
+    }
+
+    // NOTE: Returns the input events with the slot of the controller they
+    // came from.
+    pub fn translate(&mut self, event: &Event) -> Vec<(usize, InputEvent)> {
+        let mut inputs = vec![];
+
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                self.open(which);
+            }
+
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(slot) = self.slot_of(which) {
+                    self.close(slot, &mut inputs);
+                }
+            }
+
+            Event::ControllerButtonDown { which, button, .. } => {
+                if let Some(slot) = self.slot_of(which) {
+                    let controller = self.slots[slot].as_ref().unwrap();
+                    if let Some(actions) = controller.bindings.buttons.get(&button) {
+                        inputs.extend(actions.iter().map(|&action| (slot, InputEvent::Pressed(action))));
+                    }
+                }
+            }
+
+            Event::ControllerButtonUp { which, button, .. } => {
+                if let Some(slot) = self.slot_of(which) {
+                    let controller = self.slots[slot].as_ref().unwrap();
+                    if let Some(actions) = controller.bindings.buttons.get(&button) {
+                        inputs.extend(actions.iter().map(|&action| (slot, InputEvent::Released(action))));
+                    }
+                }
+            }
+
+            Event::ControllerAxisMotion { which, axis, value, .. } => {
+                if let Some(slot) = self.slot_of(which) {
+                    let controller = self.slots[slot].as_mut().unwrap();
+                    let direction = controller.bindings.axis_direction(value);
+                    let previous = controller.axis_directions.insert(axis, direction).unwrap_or(0);
+
+                    if direction != previous {
+                        if let Some(actions) = controller.bindings.axes.get(&(axis, previous)) {
+                            inputs.extend(actions.iter().map(|&action| (slot, InputEvent::Released(action))));
+                        }
+                        if let Some(actions) = controller.bindings.axes.get(&(axis, direction)) {
+                            inputs.extend(actions.iter().map(|&action| (slot, InputEvent::Pressed(action))));
+                        }
+                    }
+                }
+            }
+
+            _ => {}
+        }
+
+        inputs
+    }
+}
+
+impl Drop for Controllers {
+    fn drop(&mut self) {
+        for controller in self.slots.iter_mut().filter_map(|controller| controller.take()) {
+            unsafe {
+                sys::SDL_GameControllerClose(controller.raw);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_handling_section() {
+        let config = InputConfig::parse("[handling]\ndas = 8\narr = 0\ndas_cut = 2\nsoft_drop_factor = 0\n").unwrap();
+        assert_eq!(config.handling.das, 8);
+        assert_eq!(config.handling.arr, 0);
+        assert_eq!(config.handling.das_cut, 2);
+        assert_eq!(config.handling.soft_drop_factor, 0);
+
+        let config = InputConfig::parse("[handling]\ndas = 12 # slower\n").unwrap();
+        assert_eq!(config.handling.das, 12);
+        assert_eq!(config.handling.arr, Handling::new().arr);
+    }
+
+    #[test]
+    fn parse_handling_errors() {
+        assert_eq!(InputConfig::parse("[handling]\nlock_delay = 3\n").err().unwrap(),
+                   "line 2: unknown handling `lock_delay`");
+        assert_eq!(InputConfig::parse("[handling]\ndas = fast\n").err().unwrap(),
+                   "line 2: das must be a number");
+        assert_eq!(InputConfig::parse("[handling]\narr = -1\n").err().unwrap(),
+                   "line 2: arr must be a number");
+        assert_eq!(InputConfig::parse("[handling]\narr = 1, 2\n").err().unwrap(),
+                   "line 2: expected `arr = <number>`");
+        assert_eq!(InputConfig::parse("[handling]\ndas\n").err().unwrap(),
+                   "line 2: expected `action = inputs`");
+    }
+}
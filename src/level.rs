@@ -0,0 +1,180 @@
+use score::{LineClear, TSpin};
+
+// NOTE: Gravity is in 1/65536 cells per frame, so `G` is one cell per
+// frame and `20 * G` fills a 20 rows playfield in a single frame.
+pub const G: u32 = 65536;
+pub const INSTANT_GRAVITY: u32 = 20 * G;
+
+// NOTE: Guideline gravity curve, (0.8 - (level - 1) * 0.007) ^ (level - 1)
+// seconds per row, capped at 20G. Index 0 is level 1.
+pub const GUIDELINE_GRAVITY_TABLE: [u32; 20] = [
+    1092, 1377, 1768, 2311, 3075,
+    4169, 5759, 8107, 11634, 17026,
+    25416, 38709, 60169, 95483, 154742,
+    256187, 433425, 749597, INSTANT_GRAVITY, INSTANT_GRAVITY,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LevelGoal {
+    // NOTE: Level up every n lines.
+    Fixed(u32),
+    // NOTE: Level n needs 5 * n goal points, harder clears are worth more.
+    Variable,
+}
+
+#[derive(Clone, Debug)]
+pub struct Level {
+    level: u32,
+    goal: LevelGoal,
+    progress: u32,
+    gravity_table: Vec<u32>,
+}
+
+impl Level {
+    pub fn new(start_level: u32, goal: LevelGoal, gravity_table: Vec<u32>) -> Level {
+        assert!(start_level >= 1);
+        assert!(goal != LevelGoal::Fixed(0));
+        assert!(!gravity_table.is_empty());
+        Level {
+            level: start_level,
+            goal: goal,
+            progress: 0,
+            gravity_table: gravity_table,
+        }
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    // NOTE: Levels past the end of the table keep the last gravity.
+    pub fn gravity(&self) -> u32 {
+        let index = (self.level as usize - 1).min(self.gravity_table.len() - 1);
+        self.gravity_table[index]
+    }
+
+    fn goal(&self) -> u32 {
+        match self.goal {
+            LevelGoal::Fixed(lines) => lines,
+            LevelGoal::Variable => 5 * self.level,
+        }
+    }
+
+    // NOTE: Lines (or goal points) still needed for the next level.
+    pub fn remaining(&self) -> u32 {
+        self.goal() - self.progress
+    }
+
+    fn goal_points(clear: &LineClear, is_back_to_back: bool) -> u32 {
+        let points = match (clear.t_spin, clear.lines) {
+            (TSpin::None, 0) => 0,
+            (TSpin::None, 1) => 1,
+            (TSpin::None, 2) => 3,
+            (TSpin::None, 3) => 5,
+            (TSpin::None, _) => 8,
+
+            (TSpin::Mini, 0) => 1,
+            (TSpin::Mini, _) => 2,
+
+            (TSpin::Full, 0) => 4,
+            (TSpin::Full, 1) => 8,
+            (TSpin::Full, 2) => 12,
+            (TSpin::Full, _) => 16,
+        };
+
+        if is_back_to_back {
+            points + points / 2
+        } else {
+            points
+        }
+    }
+
+    // NOTE: Returns true if the level went up.
+    pub fn advance(&mut self, clear: &LineClear, is_back_to_back: bool) -> bool {
+        self.progress += match self.goal {
+            LevelGoal::Fixed(_) => clear.lines as u32,
+            LevelGoal::Variable => Level::goal_points(clear, is_back_to_back),
+        };
+
+        let mut is_level_up = false;
+        while self.progress >= self.goal() {
+            self.progress -= self.goal();
+            self.level += 1;
+            is_level_up = true;
+        }
+        is_level_up
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear(lines: usize, t_spin: TSpin) -> LineClear {
+        LineClear {
+            lines: lines,
+            t_spin: t_spin,
+        }
+    }
+
+    fn new_level(start_level: u32, goal: LevelGoal) -> Level {
+        Level::new(start_level, goal, GUIDELINE_GRAVITY_TABLE.to_vec())
+    }
+
+    #[test]
+    fn gravity_follows_the_guideline_curve() {
+        for level in 1..19 {
+            let seconds = (0.8 - (level - 1) as f64 * 0.007).powi(level as i32 - 1);
+            let gravity = G as f64 / (seconds * 60.0);
+            let table = GUIDELINE_GRAVITY_TABLE[level - 1] as f64;
+            assert!((table - gravity).abs() <= 1.0, "level {}: {} != {}", level, table, gravity);
+        }
+    }
+
+    #[test]
+    fn gravity_at_levels() {
+        assert_eq!(new_level(1, LevelGoal::Fixed(10)).gravity(), 1092);
+        assert_eq!(new_level(10, LevelGoal::Fixed(10)).gravity(), 17026);
+        assert_eq!(new_level(19, LevelGoal::Fixed(10)).gravity(), INSTANT_GRAVITY);
+        // NOTE: Past the table.
+        assert_eq!(new_level(30, LevelGoal::Fixed(10)).gravity(), INSTANT_GRAVITY);
+        assert_eq!(Level::new(5, LevelGoal::Fixed(10), vec![G]).gravity(), G);
+    }
+
+    #[test]
+    fn fixed_goal_levels_up_every_n_lines() {
+        let mut level = new_level(3, LevelGoal::Fixed(5));
+        assert!(!level.advance(&clear(4, TSpin::None), false));
+        assert_eq!(level.remaining(), 1);
+        assert!(level.advance(&clear(2, TSpin::None), false));
+        assert_eq!(level.level(), 4);
+        assert_eq!(level.remaining(), 4);
+
+        // NOTE: Points don't count, only lines.
+        assert!(!level.advance(&clear(0, TSpin::Full), true));
+        assert_eq!(level.remaining(), 4);
+
+        let mut level = new_level(1, LevelGoal::Fixed(1));
+        assert!(level.advance(&clear(3, TSpin::None), false));
+        assert_eq!(level.level(), 4);
+    }
+
+    #[test]
+    fn variable_goal_counts_goal_points() {
+        let mut level = new_level(1, LevelGoal::Variable);
+        assert_eq!(level.remaining(), 5);
+        assert!(!level.advance(&clear(2, TSpin::None), false));
+        assert_eq!(level.remaining(), 2);
+        // NOTE: A back-to-back tetris is worth 12, up through level 2 which
+        // needs 10.
+        assert!(level.advance(&clear(4, TSpin::None), true));
+        assert_eq!(level.level(), 3);
+        assert_eq!(level.remaining(), 15);
+    }
+
+    #[test]
+    #[should_panic]
+    fn fixed_0_is_rejected() {
+        new_level(1, LevelGoal::Fixed(0));
+    }
+}
@@ -0,0 +1,21 @@
+// NOTE: The game rules live in this library so they can be driven
+// without a window: the frontend (or a bot, a test, a replay) feeds abstract
+// `Action`s into a `Playfield` and calls `Playfield::tick` once per frame.
+// Nothing in here may depend on hammer or SDL.
+
+#[macro_use]
+pub mod block;
+pub mod timer;
+pub mod rng;
+pub mod randomizer;
+pub mod rules;
+pub mod score;
+pub mod garbage;
+pub mod level;
+pub mod handling;
+pub mod mode;
+pub mod replay;
+pub mod playfield;
+pub mod net;
+pub mod rollback;
+pub mod spectate;
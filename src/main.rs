@@ -1,669 +1,1107 @@
 extern crate rand;
 extern crate hammer;
-
-use hammer::prelude::*;
-
-use block::*;
-
+extern crate sdl2;
 #[macro_use]
-mod block;
+extern crate retris;
 
-pub enum GameState {
-    Running,
-    Paused,
-}
-
-pub struct Game {
-    state_machine: StateMachine<GameState>,
-
-    blocks: Bitmap,
-    playfield: Playfield,
-}
-
-impl Game {
-    pub fn new() -> Game {
-        let blocks = Bitmap::open("./assets/blocks.bmp").unwrap();
-        Game {
-            state_machine: StateMachine::new(GameState::Running),
-
-            playfield: Playfield::new(10, 20, blocks.height() as i32),
-            blocks: blocks,
-        }
+use hammer::prelude::*;
+use sdl2::event::WindowEvent;
+
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use retris::handling::Handling;
+use retris::mode::{Mode, ModeOptions, MODES, SPRINT_LINES};
+use retris::net::{Connection, MatchResult, NetSession, DEFAULT_PORT};
+use retris::playfield::{Input, Playfield};
+use retris::replay::{Replay, ReplayPlayer};
+use retris::rollback::RollbackSession;
+use retris::rules::Goal;
+use retris::spectate::SpectatorServer;
+
+use input::{Controllers, InputAction, InputConfig, InputEvent, KeyBindings, MenuAction};
+use records::{Records, Ranking};
+use render::PlayfieldRenderer;
+use ui::Menu;
+
+mod input;
+mod records;
+mod render;
+mod setup;
+mod ui;
+
+const SCREEN_WIDTH: i32 = 1280;
+const SCREEN_HEIGHT: i32 = 800;
+
+// NOTE: The rules advance in fixed frames, hammer gives us a variable dt.
+const FRAME_TIME: f32 = 1.0 / 60.0;
+
+// NOTE: The results and menus of a versus game are drawn in a panel this
+// wide between the two boards.
+const VERSUS_PANEL_WIDTH: i32 = 440;
+
+// NOTE: The game over animation fills one row every this many frames.
+const FILL_ROW_FRAMES: u32 = 2;
+
+const TITLE_PLAY: usize = 0;
+const TITLE_QUIT: usize = 1;
+
+const GAME_OVER_RETRY: usize = 0;
+const GAME_OVER_MENU: usize = 1;
+const ONLINE_GAME_OVER_QUIT: usize = 0;
+
+const PAUSE_RESUME: usize = 0;
+const PAUSE_RESTART: usize = 1;
+const PAUSE_SETTINGS: usize = 2;
+const PAUSE_MENU: usize = 3;
+
+const SETTINGS_GHOST: usize = 0;
+const SETTINGS_GRID: usize = 1;
+const SETTINGS_BACK: usize = 2;
+
+fn on_off(is_on: bool) -> &'static str {
+    if is_on {
+        "On"
+    } else {
+        "Off"
     }
 }
 
-impl Scene for Game {
-    fn handle_event(&mut self, event: &Event) {
-        match *self.state_machine.current_state() {
-            GameState::Running => {
-                match *event {
-                    Event::KeyDown {keycode: Some(Keycode::P), ..} => {
-                        self.state_machine.trans(push(GameState::Paused));
-                    }
-                    _ => {
-                        self.playfield.handle_event(event);
-                    }
-                }
-            }
-
-            GameState::Paused => {
-                match *event {
-                    Event::KeyDown {keycode: Some(Keycode::P), ..} => {
-                        self.state_machine.trans(pop());
-                    }
-                    _ => {}
-                }
-            }
-        }
-    }
-
-    fn update(&mut self, dt: f32) {
-        match *self.state_machine.current_state() {
-            GameState::Running => {
-                self.playfield.update(dt);
-            }
+// NOTE: Left and right change the selected option.
+fn adjust_mode_option(mode: Mode, menu: &mut Menu, options: &mut ModeOptions, direction: i32) {
+    let option = match setup::mode_options(mode).get(menu.selected()) {
+        Some(&option) => option,
+        None => return,
+    };
 
-            GameState::Paused => {}
-        }
-    }
+    setup::adjust_option(option, options, direction);
+    menu.set_item(menu.selected(), &setup::option_item(option, options));
+}
 
-    fn render(&self, renderer: &mut Renderer) {
-        self.playfield.render(renderer, 32, 32, &self.blocks);
-    }
+fn update_settings_menu(menu: &mut Menu, playfield_renderer: &PlayfieldRenderer) {
+    menu.set_item(SETTINGS_GHOST, &format!("Ghost {}", on_off(playfield_renderer.is_ghost_visible())));
+    menu.set_item(SETTINGS_GRID, &format!("Grid {}", on_off(playfield_renderer.is_grid_visible())));
 }
 
-#[derive(Debug)]
-pub enum PlayfieldState {
-    Prepare {
-        countdown: Timer,
+// NOTE: `Title`, `ModeSelect`, `ModeSetup`, `Running` and `GameOver`
+// replace each other, `Paused` and `Settings` are pushed on top of
+// `Running`.
+pub enum GameState {
+    Title {
+        menu: Menu,
     },
-    Spawn {
-        spawn_delay: Timer,
+    ModeSelect {
+        menu: Menu,
     },
-    Falling {
-        gravity_delay: Timer,
+    // NOTE: The options of the mode, then start and back.
+    ModeSetup {
+        mode: Mode,
+        menu: Menu,
     },
-    Locking {
-        lock_delay: Timer,
-        is_immediately: bool,
+    Running,
+    Paused {
+        menu: Menu,
     },
-    Breaking {
-        breaking_line_delay: Timer,
-        blink_delay: Timer,
+    // NOTE: Pushed on top of `Paused`.
+    Settings {
+        menu: Menu,
+    },
+    GameOver {
+        // NOTE: Frames since the game ended, drives the fill animation.
+        frames: u32,
+        is_new_record: bool,
+        menu: Menu,
     },
-    Lost,
 }
 
-impl PlayfieldState {
-    pub fn prepare() -> PlayfieldState {
-        PlayfieldState::Prepare {
-            countdown: Timer::new(0.0),
+impl GameState {
+    fn title() -> GameState {
+        GameState::Title {
+            menu: Menu::new(&["Play", "Quit"]),
         }
     }
 
-    pub fn spawn() -> PlayfieldState {
-        PlayfieldState::Spawn {
-            spawn_delay: Timer::new(0.0),
+    // NOTE: The modes in `MODES` order, then back.
+    fn mode_select() -> GameState {
+        let mut items = MODES.iter().map(|mode| mode.title()).collect::<Vec<_>>();
+        items.push("Back");
+        GameState::ModeSelect {
+            menu: Menu::new(&items),
         }
     }
 
-    pub fn falling() -> PlayfieldState {
-        PlayfieldState::Falling {
-            gravity_delay: Timer::new(gravity_to_delay(0.015625)),
+    fn mode_setup(mode: Mode, options: &ModeOptions) -> GameState {
+        let items = setup::mode_options(mode).iter()
+                                             .map(|&option| setup::option_item(option, options))
+                                             .collect::<Vec<_>>();
+        let mut items = items.iter().map(|item| item.as_str()).collect::<Vec<_>>();
+        items.push("Start");
+        items.push("Back");
+        GameState::ModeSetup {
+            mode: mode,
+            menu: Menu::new(&items),
         }
     }
 
-    pub fn locking() -> PlayfieldState {
-        PlayfieldState::Locking {
-            lock_delay: Timer::new(frames_to_seconds(30.0)),
-            is_immediately: false,
+    fn paused() -> GameState {
+        GameState::Paused {
+            menu: Menu::new(&["Resume", "Restart", "Settings", "Menu"]),
         }
     }
 
-    pub fn locking_immediately() -> PlayfieldState {
-        PlayfieldState::Locking {
-            lock_delay: Timer::new(frames_to_seconds(0.0)),
-            is_immediately: true,
+    fn settings(playfield_renderer: &PlayfieldRenderer) -> GameState {
+        let mut menu = Menu::new(&["", "", "Back"]);
+        update_settings_menu(&mut menu, playfield_renderer);
+        GameState::Settings {
+            menu: menu,
         }
     }
 
-    pub fn breaking() -> PlayfieldState {
-        PlayfieldState::Breaking {
-            breaking_line_delay: Timer::new(0.25),
-            blink_delay: Timer::new(0.08),
+    fn game_over(is_new_record: bool) -> GameState {
+        GameState::GameOver {
+            frames: 0,
+            is_new_record: is_new_record,
+            menu: Menu::new(&["Retry", "Menu"]),
         }
     }
 
-    pub fn lost() -> PlayfieldState {
-        PlayfieldState::Lost
+    // NOTE: A network game is played once, there is no rematch.
+    fn online_game_over() -> GameState {
+        GameState::GameOver {
+            frames: 0,
+            is_new_record: false,
+            menu: Menu::new(&["Quit"]),
+        }
     }
 }
 
-fn frames_to_seconds(frames: f32) -> f32 {
-    // NOTE: Assuming the game run under 60 FPS.
-    let fps = 60.0;
-    frames / fps
-}
+pub struct Game {
+    state_machine: StateMachine<GameState>,
 
-fn gravity_to_delay(gravity: f32) -> f32 {
-    // NOTE: gravity is how many cells per frame, so the inverse
-    // is how many frames per cell.
-    frames_to_seconds(1.0 / gravity)
-}
+    // NOTE: From `[handling]` in bindings.cfg, used by every local game.
+    handling: Handling,
+    key_bindings: KeyBindings,
+    // NOTE: The playfield keys of each player in versus.
+    versus_keyboards: Vec<KeyBindings>,
+    controllers: Controllers,
 
-pub struct Playfield {
-    state_machine: StateMachine<PlayfieldState>,
-    raw: PlayfieldRaw,
+    blocks: Bitmap,
+    mode: Mode,
+    options: ModeOptions,
+    // NOTE: Every game uses this seed if set, otherwise a random one.
+    seed: Option<u64>,
+    playfield: Playfield,
+    // NOTE: Player 2 in versus, `playfield` is player 1.
+    opponent: Option<Playfield>,
+    playfield_renderer: PlayfieldRenderer,
+
+    records: Records,
+
+    // NOTE: Every local game is recorded, and saved when it ends or is left
+    // for another one.
+    replay: Replay,
+    is_replay_saved: bool,
+    // NOTE: Set when watching a replay, local input is ignored.
+    playback: Option<ReplayPlayer>,
+    // NOTE: Set in a network game, `opponent` mirrors the remote player.
+    net: Option<NetSession>,
+    // NOTE: Set in a rollback network game, it plays both boards and
+    // `playfield` and `opponent` are copies of them for the rest of the game.
+    rollback: Option<RollbackSession>,
+    // NOTE: Set with `--spectate`, the playfields go out every frame.
+    spectate: Option<SpectatorServer>,
+
+    frame_accumulator: f32,
 }
 
-pub struct PlayfieldRaw {
-    block: Block,
+impl Game {
+    // NOTE: Starts at the title screen, the playfield is replaced when a
+    // mode is picked.
+    pub fn new(seed: Option<u64>, input_config: InputConfig) -> Game {
+        let mode = Mode::Endless;
+        let playfield = Playfield::new(&mode.rules(&ModeOptions::new()), seed.unwrap_or(0));
+        Game::with_playback(GameState::title(), mode, seed, playfield, input_config, None)
+    }
 
-    falling_block: Option<FallingBlock>,
+    pub fn replay(replay: Replay, input_config: InputConfig) -> Game {
+        let playback = ReplayPlayer::new(replay);
+        let playfield = playback.create_playfield();
+        let mode = playback.replay().mode;
+        let opponent = if mode.player_count() > 1 {
+            Some(playback.create_playfield())
+        } else {
+            None
+        };
+        let mut game = Game::with_playback(GameState::Running, mode, None, playfield, input_config, Some(playback));
+        game.opponent = opponent;
+        game
+    }
 
-    generator: BlockTemplateGenerator,
-    block_template: BlockTemplate,
+    // NOTE: Versus against a remote player, the game starts right away. Both
+    // sides play the inputs of both players, so the handling is the default
+    // one and not `[handling]`.
+    pub fn online(connection: Connection, seed: u64, input_config: InputConfig) -> Game {
+        let mode = Mode::Versus;
+        let rules = mode.rules(&ModeOptions::new());
+        let playfield = Playfield::new(&rules, seed);
+        let mut game = Game::with_playback(GameState::Running, mode, Some(seed), playfield, input_config, None);
+        game.opponent = Some(Playfield::new(&rules, seed));
+        game.net = Some(NetSession::new(connection));
+        game
+    }
 
-    held_template: Option<BlockTemplateRef>,
-    can_hold_falling_block: bool,
+    // NOTE: Versus against a remote player with rollback, see `online`.
+    pub fn online_rollback(session: RollbackSession, seed: u64, input_config: InputConfig) -> Game {
+        let local_player = session.local_player();
+        let playfield = session.versus().playfield(local_player).clone();
+        let mut game = Game::with_playback(GameState::Running, Mode::Versus, Some(seed), playfield, input_config, None);
+        game.opponent = Some(session.versus().playfield(1 - local_player).clone());
+        game.rollback = Some(session);
+        game
+    }
 
-    max_lock_delay: Timer,
+    fn is_online(&self) -> bool {
+        self.net.is_some() || self.rollback.is_some()
+    }
 
-    breaking_lines: Vec<usize>,
-    is_breaking_lines_visible: bool,
+    fn with_playback(state: GameState,
+                     mode: Mode,
+                     seed: Option<u64>,
+                     playfield: Playfield,
+                     input_config: InputConfig,
+                     playback: Option<ReplayPlayer>)
+                     -> Game {
+        let blocks = Bitmap::open("./assets/blocks.bmp").unwrap();
+        Game {
+            state_machine: StateMachine::new(state),
+
+            handling: input_config.handling,
+            key_bindings: input_config.keyboard.clone(),
+            versus_keyboards: input_config.versus_keyboards.clone(),
+            controllers: Controllers::new(input_config),
+
+            mode: mode,
+            options: ModeOptions::new(),
+            seed: seed,
+            replay: Replay::new(mode, playfield.rules(), playfield.seed()),
+            playfield: playfield,
+            opponent: None,
+            playfield_renderer: PlayfieldRenderer::new(blocks.height() as i32),
+            blocks: blocks,
 
-    block_size_in_pixels: i32,
-}
+            records: Records::load("./records.cfg"),
 
-impl PlayfieldRaw {
-    pub fn new(width: usize, height: usize, block_size_in_pixels: i32) -> PlayfieldRaw {
-        let block_template = BlockTemplate::new();
-        PlayfieldRaw {
-            block: Block::new(width, height),
+            is_replay_saved: false,
+            playback: playback,
+            net: None,
+            rollback: None,
+            spectate: None,
 
-            falling_block: None,
+            frame_accumulator: 0.0,
+        }
+    }
 
-            generator: BlockTemplateGenerator::new(&block_template),
-            block_template: block_template,
+    fn handle_playfield_input(&mut self, player: usize, input: Input) {
+        if self.playback.is_some() {
+            return;
+        }
 
-            held_template: None,
-            can_hold_falling_block: true,
+        match player {
+            0 => {
+                self.replay.record(self.playfield.frame(), 0, input);
+                match (self.net.as_mut(), self.rollback.as_mut()) {
+                    (Some(net), _) => net.handle_input(&mut self.playfield, input),
+                    (None, Some(rollback)) => rollback.handle_input(input),
+                    (None, None) => self.playfield.handle_input(input),
+                }
+            }
+            1 => {
+                if let Some(ref mut opponent) = self.opponent {
+                    self.replay.record(opponent.frame(), 1, input);
+                    opponent.handle_input(input);
+                }
+            }
+            _ => {}
+        }
+    }
 
-            max_lock_delay: Timer::new(frames_to_seconds(60.0)),
+    // NOTE: Player 1 first.
+    fn playfields(&self) -> Vec<&Playfield> {
+        let mut playfields = vec![&self.playfield];
+        playfields.extend(self.opponent.as_ref());
+        playfields
+    }
 
-            breaking_lines: vec![],
-            is_breaking_lines_visible: true,
+    // NOTE: The bottom left corner of each player's playfield, a single
+    // playfield is centered.
+    fn playfield_pos(&self, player: usize) -> (i32, i32) {
+        let (width, _) = self.playfield_renderer.size_in_pixels(&self.playfield);
+        let center_x = match self.opponent {
+            Some(_) => SCREEN_WIDTH / 4 * (1 + 2 * player as i32),
+            None => SCREEN_WIDTH / 2,
+        };
+        (center_x - width / 2, 32)
+    }
 
-            block_size_in_pixels: block_size_in_pixels,
+    // NOTE: Where the pause and game over screens are drawn, over the cells
+    // or between the boards in versus.
+    fn panel_rect(&self) -> (i32, i32, i32, i32) {
+        let (x, y) = self.playfield_pos(0);
+        let (x0, y0, x1, y1) = self.playfield_renderer.cells_rect(&self.playfield, x, y);
+        match self.opponent {
+            Some(_) => (SCREEN_WIDTH / 2 - VERSUS_PANEL_WIDTH / 2, y0, SCREEN_WIDTH / 2 + VERSUS_PANEL_WIDTH / 2, y1),
+            None => (x0, y0, x1, y1),
         }
     }
 
-    fn spawn_falling_block(&mut self) {
-        let template = self.generator.generate(&self.block_template);
-        self.spawn_falling_block_with(template);
-    }
+    fn save_replay(&mut self) {
+        // NOTE: Online games are not saved, see `replay`.
+        if self.playback.is_some() || self.is_online() || self.is_replay_saved {
+            return;
+        }
 
-    fn spawn_falling_block_with(&mut self, template: BlockTemplateRef) {
-        let bottom = self.block_template.block(&template).bottom();
-        self.falling_block = Some(FallingBlock::new(3, self.block.height as i32 - bottom as i32, template));
-    }
+        self.is_replay_saved = true;
+        self.replay.frames = self.playfield.frame();
 
-    pub fn drop_falling_block(&mut self) {
-        if let Some(ref mut falling_block) = self.falling_block {
-            let (x, y) = self.block.get_ghost_block_pos(falling_block.x,
-                                                        falling_block.y,
-                                                        self.block_template.block(&falling_block.template));
-            falling_block.move_to(x, y);
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let path = format!("./replays/{}-{}.replay", timestamp, self.replay.seed);
+        match self.replay.save(&path) {
+            Ok(_) => println!("replay saved to {}", path),
+            Err(e) => println!("failed to save replay, {}", e),
         }
     }
 
-    pub fn hold_falling_block(&mut self) {
-        if self.can_hold_falling_block {
-            let falling_block = self.falling_block.take();
-            if let Some(falling_block) = falling_block {
-                let mut template = falling_block.template;
-                template.order = 0;
-                if let Some(held_template) = self.held_template.take() {
-                    self.spawn_falling_block_with(held_template);
-                } else {
-                    self.spawn_falling_block();
-                }
-                self.held_template = Some(template);
-                self.can_hold_falling_block = false;
-            }
+    // NOTE: Saves a game that is left before it ended, unless nothing was
+    // pressed yet.
+    fn save_left_replay(&mut self) {
+        if !self.replay.events.is_empty() {
+            self.save_replay();
         }
     }
 
-    pub fn rotate_falling_block(&mut self, direction: i32) {
-        if let Some(ref mut falling_block) = self.falling_block {
-            let mut new_template = falling_block.template;
-            if direction > 0 {
-                new_template.rrotate();
-            } else {
-                new_template.lrotate();
-            }
+    fn fill_frames(&self) -> u32 {
+        self.playfield.raw().block().height as u32 * FILL_ROW_FRAMES
+    }
 
-            let table = self.block_template.wall_kick_table(&falling_block.template,
-                                                            &new_template);
-            for &(dx, dy) in table {
-                if self.block.is_valid_position(falling_block.x + dx,
-                                                falling_block.y + dy,
-                                                &self.block_template
-                                                     .block(&new_template)) {
-                    falling_block.move_by(dx, dy);
-                    falling_block.template = new_template;
-                    break;
-                }
+    fn next_seed(&self) -> u64 {
+        match self.seed {
+            Some(seed) => seed,
+            None => {
+                let seed = rand::random::<u64>();
+                println!("seed: {}", seed);
+                seed
             }
         }
     }
 
-    pub fn can_move_falling_block_by(&self, dx: i32, dy: i32) -> bool {
-        if let Some(ref falling_block) = self.falling_block {
-            self.block.is_valid_position(falling_block.x + dx,
-                                         falling_block.y + dy,
-                                         &self.block_template
-                                              .block(&falling_block.template))
+    fn start(&mut self, mode: Mode) {
+        self.save_left_replay();
+        let seed = self.next_seed();
+        self.mode = mode;
+        self.playback = None;
+        let mut rules = mode.rules(&self.options);
+        rules.handling = self.handling;
+        self.playfield = Playfield::new(&rules, seed);
+        // NOTE: Both players get the same seed, so the same pieces.
+        self.opponent = if mode.player_count() > 1 {
+            Some(Playfield::new(self.playfield.rules(), seed))
         } else {
-            false
-        }
+            None
+        };
+        self.replay = Replay::new(self.mode, self.playfield.rules(), seed);
+        self.is_replay_saved = false;
+        self.frame_accumulator = 0.0;
+        self.state_machine.trans(switch(GameState::Running));
     }
 
-    pub fn move_falling_block_by(&mut self, dx: i32, dy: i32) {
-        if self.can_move_falling_block_by(dx, dy) {
-            self.falling_block.as_mut().unwrap().move_by(dx, dy);
+    // NOTE: Plays again with the same rules, a replay starts over from the
+    // beginning.
+    fn retry(&mut self) {
+        self.save_left_replay();
+        let seed = match self.playback {
+            Some(ref mut playback) => {
+                *playback = ReplayPlayer::new(playback.replay().clone());
+                playback.replay().seed
+            }
+            None => self.next_seed(),
+        };
+
+        self.playfield.reset(seed);
+        if let Some(ref mut opponent) = self.opponent {
+            opponent.reset(seed);
         }
+        self.replay = Replay::new(self.mode, self.playfield.rules(), seed);
+        self.is_replay_saved = false;
+        self.frame_accumulator = 0.0;
+        self.state_machine.trans(switch(GameState::Running));
     }
 
-    pub fn is_falling_block_out_of_bounds(&self) -> bool {
-        if let Some(ref falling_block) = self.falling_block {
-            self.block.is_out_of_bounds(falling_block.x,
-                                        falling_block.y,
-                                        self.block_template.block(&falling_block.template))
-        } else {
-            true
+    fn pause(&mut self) {
+        // NOTE: The remote player can't be paused.
+        if self.is_online() {
+            return;
         }
-    }
 
-    pub fn lock_falling_block(&mut self) {
-        assert!(!self.is_falling_block_out_of_bounds());
-        let falling_block = self.falling_block.take();
-        if let Some(falling_block) = falling_block {
-            self.block.set_with_block(falling_block.x,
-                                      falling_block.y,
-                                      self.block_template.block(&falling_block.template));
-            self.can_hold_falling_block = true;
-            self.max_lock_delay.reset();
+        if let GameState::Running = *self.state_machine.current_state() {
+            self.state_machine.trans(push(GameState::paused()));
         }
     }
 
-    pub fn has_lines_to_break(&mut self) -> bool {
-        self.breaking_lines = self.block.get_break_lines();
-        self.breaking_lines.len() > 0
-    }
+    fn handle_input(&mut self, player: usize, input_event: InputEvent) {
+        let fill_frames = self.fill_frames();
+        let mut selected = None;
 
-    pub fn blink_breaking_lines(&mut self) {
-        self.is_breaking_lines_visible = !self.is_breaking_lines_visible;
-    }
+        match *self.state_machine.current_state_mut() {
+            GameState::Title {ref mut menu} => {
+                if let InputEvent::Pressed(InputAction::Menu(action)) = input_event {
+                    selected = menu.handle(action);
+                }
+            }
 
-    pub fn break_lines(&mut self) {
-        self.block.break_lines();
-        self.breaking_lines.clear();
-        self.is_breaking_lines_visible = true;
-    }
+            GameState::ModeSelect {ref mut menu} => {
+                match input_event {
+                    InputEvent::Pressed(InputAction::Menu(MenuAction::Back)) => {
+                        self.state_machine.trans(switch(GameState::title()));
+                    }
+                    InputEvent::Pressed(InputAction::Menu(action)) => {
+                        selected = menu.handle(action);
+                    }
+                    _ => {}
+                }
+            }
 
-    fn width_in_pixels(&self) -> i32 {
-        self.block.width as i32 * self.block_size_in_pixels
-    }
+            GameState::ModeSetup {mode, ref mut menu} => {
+                match input_event {
+                    InputEvent::Pressed(InputAction::Menu(MenuAction::Back)) => {
+                        self.state_machine.trans(switch(GameState::mode_select()));
+                    }
+                    InputEvent::Pressed(InputAction::Menu(MenuAction::Left)) => {
+                        adjust_mode_option(mode, menu, &mut self.options, -1);
+                    }
+                    InputEvent::Pressed(InputAction::Menu(MenuAction::Right)) => {
+                        adjust_mode_option(mode, menu, &mut self.options, 1);
+                    }
+                    InputEvent::Pressed(InputAction::Menu(action)) => {
+                        selected = menu.handle(action);
+                    }
+                    _ => {}
+                }
+            }
 
-    fn height_in_pixels(&self) -> i32 {
-        self.block.height as i32 * self.block_size_in_pixels
-    }
+            GameState::Running => {
+                match input_event {
+                    InputEvent::Pressed(InputAction::Pause) => {
+                        self.pause();
+                    }
+                    InputEvent::Pressed(InputAction::Playfield(action)) => {
+                        self.handle_playfield_input(player, Input::Pressed(action));
+                    }
+                    InputEvent::Released(InputAction::Playfield(action)) => {
+                        self.handle_playfield_input(player, Input::Released(action));
+                    }
+                    _ => {}
+                }
+            }
+
+            GameState::Paused {ref mut menu} => {
+                match input_event {
+                    InputEvent::Pressed(InputAction::Pause) |
+                    InputEvent::Pressed(InputAction::Menu(MenuAction::Back)) => {
+                        self.state_machine.trans(pop());
+                    }
+                    InputEvent::Pressed(InputAction::Menu(action)) => {
+                        selected = menu.handle(action);
+                    }
+                    // NOTE: Keep the held keys in sync, otherwise a key
+                    // released while paused would auto repeat forever.
+                    InputEvent::Released(InputAction::Playfield(action)) => {
+                        self.handle_playfield_input(player, Input::Released(action));
+                    }
+                    _ => {}
+                }
+            }
 
-    fn render_held_blocks(&self, renderer: &mut SoftwareRenderer, x: i32, y: i32, blocks_bitmap: &Bitmap) {
-        if let Some(held_template) = self.held_template {
-            let block = self.block_template.block(&held_template);
+            GameState::Settings {ref mut menu} => {
+                match input_event {
+                    InputEvent::Pressed(InputAction::Menu(MenuAction::Back)) => {
+                        self.state_machine.trans(pop());
+                    }
+                    InputEvent::Pressed(InputAction::Menu(action)) => {
+                        selected = menu.handle(action);
+                    }
+                    InputEvent::Released(InputAction::Playfield(action)) => {
+                        self.handle_playfield_input(player, Input::Released(action));
+                    }
+                    _ => {}
+                }
+            }
 
-            for (col, row, cell) in block_iter!(block) {
-                let x_offset = col as i32 * self.block_size_in_pixels;
-                let y_offset = (block.height - row) as i32 * self.block_size_in_pixels;
-                let x = x + x_offset;
-                let y = y + (self.height_in_pixels() - y_offset);
-                renderer.blit_sub_bitmap(x + 1, y + 1,
-                                         self.block_size_in_pixels * cell.index,
-                                         0,
-                                         self.block_size_in_pixels,
-                                         self.block_size_in_pixels, blocks_bitmap);
+            GameState::GameOver {frames, ref mut menu, ..} => {
+                // NOTE: Wait for the animation, so the key that ended the
+                // game doesn't pick an option by accident.
+                if frames >= fill_frames {
+                    if let InputEvent::Pressed(InputAction::Menu(action)) = input_event {
+                        selected = menu.handle(action);
+                    }
+                }
             }
         }
+
+        if let Some(selected) = selected {
+            self.handle_menu(selected);
+        }
     }
 
-    fn render_falling_block(&self, renderer: &mut SoftwareRenderer, x: i32, y: i32, blocks_bitmap: &Bitmap) {
-        let x = self.x_offset_for_cells(x);
-        if let Some(ref falling_block) = self.falling_block {
-            let block = self.block_template.block(&falling_block.template);
+    fn handle_menu(&mut self, selected: usize) {
+        match *self.state_machine.current_state() {
+            GameState::Title {..} => {
+                match selected {
+                    TITLE_PLAY => self.state_machine.trans(switch(GameState::mode_select())),
+                    TITLE_QUIT => std::process::exit(0),
+                    _ => {}
+                }
+            }
 
-            for (col, row, cell) in block_iter!(block) {
-                // Simply clip the block
-                if falling_block.y + (row as i32) < self.block.height as i32 {
-                    let x_offset = (falling_block.x + col as i32) * self.block_size_in_pixels;
-                    let y_offset = (falling_block.y + row as i32) * self.block_size_in_pixels;
-                    let x = x + x_offset;
-                    let y = y + y_offset;
-                    renderer.blit_sub_bitmap(x + 1, y + 1,
-                                             self.block_size_in_pixels * cell.index,
-                                             0,
-                                             self.block_size_in_pixels,
-                                             self.block_size_in_pixels, blocks_bitmap);
+            GameState::ModeSelect {..} => {
+                match MODES.get(selected) {
+                    Some(&mode) if setup::mode_options(mode).is_empty() => self.start(mode),
+                    Some(&mode) => self.state_machine.trans(switch(GameState::mode_setup(mode, &self.options))),
+                    None => self.state_machine.trans(switch(GameState::title())),
                 }
             }
-        }
-    }
 
-    fn render_ghost_block(&self, renderer: &mut SoftwareRenderer, x: i32, y: i32) {
-        let x = self.x_offset_for_cells(x);
-        if let Some(ref falling_block) = self.falling_block {
-            let block = self.block_template.block(&falling_block.template);
-            let (ghost_x, ghost_y) = self.block
-                                         .get_ghost_block_pos(falling_block.x,
-                                                              falling_block.y,
-                                                              self.block_template.block(&falling_block.template));
-
-            for (col, row, cell) in block_iter!(block) {
-                // Simply clip the block
-                if ghost_y + (row as i32) < self.block.height as i32 {
-                    let x_offset = (ghost_x + col as i32) * self.block_size_in_pixels;
-                    let y_offset = (ghost_y + row as i32) * self.block_size_in_pixels;
-                    let x = x + x_offset;
-                    let y = y + y_offset;
-                    renderer.rect(x + 1,
-                                  y + 1,
-                                  x + self.block_size_in_pixels - 1,
-                                  y + self.block_size_in_pixels - 1,
-                                  cell.color);
+            GameState::ModeSetup {mode, ..} => {
+                let option_count = setup::mode_options(mode).len();
+                if selected == option_count {
+                    self.start(mode);
+                } else if selected == option_count + 1 {
+                    self.state_machine.trans(switch(GameState::mode_select()));
                 }
             }
-        }
-    }
 
-    fn render_cells(&self, renderer: &mut SoftwareRenderer, x: i32, y: i32, blocks_bitmap: &Bitmap) {
-        let x = self.x_offset_for_cells(x);
-        for (col, row, cell) in block_iter!(self.block) {
-            let mut alpha = 1.0;
-            if self.breaking_lines.contains(&row) {
-                if !self.is_breaking_lines_visible {
-                    continue;
+            GameState::Paused {..} => {
+                match selected {
+                    PAUSE_RESUME => self.state_machine.trans(pop()),
+                    PAUSE_RESTART => {
+                        self.state_machine.trans(pop());
+                        self.retry();
+                    }
+                    PAUSE_SETTINGS => {
+                        let settings = GameState::settings(&self.playfield_renderer);
+                        self.state_machine.trans(push(settings));
+                    }
+                    PAUSE_MENU => {
+                        self.save_left_replay();
+                        self.state_machine.trans(pop());
+                        self.state_machine.trans(switch(GameState::mode_select()));
+                    }
+                    _ => {}
+                }
+            }
+
+            GameState::Settings {..} => {
+                match selected {
+                    SETTINGS_GHOST => {
+                        let is_visible = self.playfield_renderer.is_ghost_visible();
+                        self.playfield_renderer.set_ghost_visible(!is_visible);
+                    }
+                    SETTINGS_GRID => {
+                        let is_visible = self.playfield_renderer.is_grid_visible();
+                        self.playfield_renderer.set_grid_visible(!is_visible);
+                    }
+                    SETTINGS_BACK => {
+                        self.state_machine.trans(pop());
+                        return;
+                    }
+                    _ => {}
+                }
+
+                if let GameState::Settings {ref mut menu} = *self.state_machine.current_state_mut() {
+                    update_settings_menu(menu, &self.playfield_renderer);
                 }
+            }
 
-                alpha = 0.5;
+            GameState::GameOver {..} if self.is_online() => {
+                if selected == ONLINE_GAME_OVER_QUIT {
+                    std::process::exit(0);
+                }
             }
 
-            let x_offset = (col as i32) * self.block_size_in_pixels;
-            let y_offset = (row as i32) * self.block_size_in_pixels;
-            let x = x + x_offset;
-            let y = y + y_offset;
-            renderer.blit_sub_bitmap_alpha(x + 1, y + 1,
-                                           self.block_size_in_pixels * cell.index,
-                                           0,
-                                           self.block_size_in_pixels,
-                                           self.block_size_in_pixels, blocks_bitmap,
-                                           alpha);
-        }
-    }
+            GameState::GameOver {..} => {
+                match selected {
+                    GAME_OVER_RETRY => self.retry(),
+                    GAME_OVER_MENU => self.state_machine.trans(switch(GameState::mode_select())),
+                    _ => {}
+                }
+            }
 
-    fn render_grids(&self, renderer: &mut SoftwareRenderer, x: i32, y: i32, color: RGBA) {
-        let x = self.x_offset_for_cells(x);
-        for row in 1..self.block.height {
-            let y_offset = row as i32 * self.block_size_in_pixels;
-            renderer.hline(y + y_offset, x, x + self.width_in_pixels(), color);
+            GameState::Running => {}
         }
+    }
 
-        for col in 1..self.block.width {
-            let x_offset = col as i32 * self.block_size_in_pixels;
-            renderer.vline(x + x_offset, y, y + self.height_in_pixels(), color);
+    fn render_playfields(&self, renderer: &mut Renderer) {
+        for (player, playfield) in self.playfields().into_iter().enumerate() {
+            let (x, y) = self.playfield_pos(player);
+            self.playfield_renderer.render(renderer, playfield, x, y, &self.blocks);
+            self.render_hud(renderer, playfield, x, y);
         }
     }
 
-    fn render_borders(&self, renderer: &mut SoftwareRenderer, x: i32, y: i32, color: RGBA) {
-        let x = self.x_offset_for_cells(x);
-        renderer.rect(x, y,
-                      x + self.width_in_pixels(),
-                      y + self.height_in_pixels(),
-                      color);
+    fn render_title(&self, renderer: &mut Renderer, menu: &Menu) {
+        let center_x = SCREEN_WIDTH / 2;
+        ui::render_text_centered(renderer, center_x, SCREEN_HEIGHT - 250, "Retris", 16, rgba(1.0, 1.0, 1.0, 1.0));
+        menu.render(renderer, center_x, 300, 4);
     }
 
-    fn render_next_blocks(&self, renderer: &mut SoftwareRenderer, x: i32, y: i32, blocks_bitmap: &Bitmap) {
-        let x = self.x_offset_for_next_blocks(x);
-        for (i, template) in self.generator.next_templates().iter().enumerate() {
-            let block = self.block_template.block(template);
+    fn render_mode_select(&self, renderer: &mut Renderer, menu: &Menu) {
+        let center_x = SCREEN_WIDTH / 2;
+        let white = rgba(1.0, 1.0, 1.0, 1.0);
+        ui::render_text_centered(renderer, center_x, SCREEN_HEIGHT - 150, "Select mode", 6, white);
+        menu.render(renderer, center_x, SCREEN_HEIGHT - 300, 4);
 
-            for (col, row, cell) in block_iter!(block) {
-                let x_offset = col as i32 * self.block_size_in_pixels;
-                let y_offset = (block.height - row) as i32 * self.block_size_in_pixels;
-                let x = x + x_offset;
-                let y = y + (self.height_in_pixels() - y_offset) -
-                        i as i32 * 4 * self.block_size_in_pixels;
-                renderer.blit_sub_bitmap(x + 1, y + 1,
-                                         self.block_size_in_pixels * cell.index,
-                                         0,
-                                         self.block_size_in_pixels,
-                                         self.block_size_in_pixels, blocks_bitmap);
-            }
+        if let Some(mode) = MODES.get(menu.selected()) {
+            ui::render_text_centered(renderer, center_x, 100, mode.description(), 3, rgba(0.7, 0.7, 0.7, 1.0));
         }
     }
 
-    fn x_offset_for_cells(&self, x: i32) -> i32 {
-        x + 5 * self.block_size_in_pixels
-    }
+    fn render_mode_setup(&self, renderer: &mut Renderer, mode: Mode, menu: &Menu) {
+        let center_x = SCREEN_WIDTH / 2;
+        let white = rgba(1.0, 1.0, 1.0, 1.0);
+        ui::render_text_centered(renderer, center_x, SCREEN_HEIGHT - 150, mode.title(), 6, white);
+        menu.render(renderer, center_x, SCREEN_HEIGHT - 300, 4);
 
-    fn x_offset_for_next_blocks(&self, x: i32) -> i32 {
-        self.x_offset_for_cells(x) + self.width_in_pixels() + self.block_size_in_pixels
+        if menu.selected() < setup::mode_options(mode).len() {
+            ui::render_text_centered(renderer, center_x, 100, "Left and right to change", 3, rgba(0.7, 0.7, 0.7, 1.0));
+        }
     }
 
-    pub fn render(&self, renderer: &mut SoftwareRenderer, x: i32, y: i32, blocks_bitmap: &Bitmap) {
-        self.render_held_blocks(renderer, x, y, blocks_bitmap);
-        if !self.falling_block.is_none() {
-            self.render_ghost_block(renderer, x, y);
-            self.render_falling_block(renderer, x, y, blocks_bitmap);
+    // NOTE: The board is hidden while paused, no planning ahead.
+    fn render_paused(&self, renderer: &mut Renderer, title: &str, menu: &Menu) {
+        let (x0, y0, x1, y1) = self.panel_rect();
+        let center_x = (x0 + x1) / 2;
+        let white = rgba(1.0, 1.0, 1.0, 1.0);
+        renderer.rect(x0, y0, x1, y1, white);
+
+        let y = y1 - 80;
+        ui::render_text_centered(renderer, center_x, y, title, 5, white);
+
+        for (i, line) in self.results().iter().enumerate() {
+            ui::render_text_centered(renderer, center_x, y - 80 - i as i32 * 32, line, 3, white);
         }
-        self.render_cells(renderer, x, y, blocks_bitmap);
-        self.render_grids(renderer, x, y, rgba(0.2, 0.2, 0.2, 1.0));
-        self.render_borders(renderer, x, y, rgba(1.0, 1.0, 1.0, 1.0));
-        self.render_next_blocks(renderer, x, y, blocks_bitmap);
+
+        menu.render(renderer, center_x, y0 + 200, 3);
     }
 
-    fn handle_common_event(&mut self, event: &Event) {
-        match *event {
-            Event::KeyDown {keycode: Some(Keycode::Up), ..} => {
-                self.rotate_falling_block(1);
+    fn results(&self) -> Vec<String> {
+        let score = self.playfield.score();
+        let time = format!("Time {}", ui::format_time(self.playfield.time()));
+        match self.mode {
+            Mode::Versus => {
+                let opponent = self.opponent.as_ref().unwrap_or(&self.playfield);
+                vec![time,
+                     format!("Lines {} - {}", score.lines(), opponent.score().lines()),
+                     format!("Sent {} - {}", self.playfield.garbage_sent(), opponent.garbage_sent()),
+                     format!("Pieces {} - {}", self.playfield.pieces(), opponent.pieces())]
             }
-            Event::KeyDown {keycode: Some(Keycode::Z), ..} => {
-                self.rotate_falling_block(-1);
+            Mode::Sprint => vec![time, format!("Lines {}/{}", score.lines(), SPRINT_LINES)],
+            Mode::Ultra => vec![format!("Score {}", score.score()), format!("Lines {}", score.lines())],
+            Mode::Cheese => {
+                let rows = self.playfield.rules().garbage_rows;
+                let cleared = rows - self.playfield.raw().block().garbage_rows().min(rows);
+                vec![time, format!("Garbage {}/{}", cleared, rows), format!("Pieces {}", self.playfield.pieces())]
             }
-            Event::KeyDown {keycode: Some(Keycode::Left), ..} => {
-                self.move_falling_block_by(-1, 0);
+            Mode::Survival => {
+                vec![format!("Survived {}", ui::format_time(self.playfield.time())),
+                     format!("Lines {}", score.lines()),
+                     format!("Pieces {}", self.playfield.pieces())]
             }
-            Event::KeyDown {keycode: Some(Keycode::Right), ..} => {
-                self.move_falling_block_by(1, 0);
+            Mode::Marathon | Mode::Endless => {
+                vec![format!("Score {}", score.score()),
+                     format!("Level {}", self.playfield.level().level()),
+                     format!("Lines {}", score.lines()),
+                     time]
             }
-            Event::KeyDown {keycode: Some(Keycode::C), ..} => {
-                self.hold_falling_block();
-            }
-            _ => {}
         }
     }
 
-    pub fn handle_event(&mut self, event: &Event, state: &mut PlayfieldState) -> Option<Trans<PlayfieldState>> {
-        match *state {
-            PlayfieldState::Falling { ref mut gravity_delay } => {
-                match *event {
-                    Event::KeyDown {keycode: Some(Keycode::Down), ..} => {
-                        self.move_falling_block_by(0, -1);
-                        gravity_delay.reset();
-                    }
-                    Event::KeyDown {keycode: Some(Keycode::Space), ..} => {
-                        self.drop_falling_block();
-                        return Some(switch(PlayfieldState::locking_immediately()));
-                    }
-                    _ => {
-                        self.handle_common_event(event);
-                    }
-                }
-
-                if !self.can_move_falling_block_by(0, -1) {
-                    return Some(switch(PlayfieldState::locking()));
-                }
+    fn versus_result(&self) -> &'static str {
+        let is_lost = self.playfield.is_lost();
+        let is_opponent_lost = self.opponent.as_ref().is_some_and(|opponent| opponent.is_lost());
+        if let Some(ref rollback) = self.rollback {
+            // NOTE: Ended without a confirmed end, the remote is gone.
+            if !rollback.is_ended() {
+                return "Disconnected";
             }
+            return match (is_lost, is_opponent_lost) {
+                (true, true) => "Draw",
+                (true, false) => "You lose",
+                (false, true) => "You win",
+                (false, false) => "Game over",
+            };
+        }
+        if let Some(ref net) = self.net {
+            // NOTE: A match server has the last word.
+            return match (net.result(), is_lost, net.is_remote_lost()) {
+                (Some(MatchResult::Win), ..) | (None, false, true) => "You win",
+                (Some(MatchResult::Lose), ..) | (None, true, _) => "You lose",
+                (Some(MatchResult::Draw), ..) => "Draw",
+                (None, false, false) if net.error().is_some() => "Disconnected",
+                (None, false, false) => "Game over",
+            };
+        }
 
-            PlayfieldState::Locking { .. } => {
-                self.handle_common_event(event);
+        match (is_lost, is_opponent_lost) {
+            (true, true) => "Draw",
+            (true, false) => "Player 2 wins",
+            (false, true) => "Player 1 wins",
+            (false, false) => "Game over",
+        }
+    }
 
-                if self.can_move_falling_block_by(0, -1) {
-                    return Some(switch(PlayfieldState::falling()));
-                }
+    fn format_record(&self, value: u64) -> String {
+        match records::ranking(self.mode) {
+            Ranking::LowestTime | Ranking::LongestTime => ui::format_time(value as u32),
+            Ranking::HighestScore => value.to_string(),
+        }
+    }
+
+    // NOTE: Mode specific stats, left of the playfield under the held
+    // piece.
+    fn render_hud(&self, renderer: &mut Renderer, playfield: &Playfield, x: i32, y: i32) {
+        let (x, _, _, y) = self.playfield_renderer.hud_rect(playfield, x, y);
+        let white = rgba(1.0, 1.0, 1.0, 1.0);
+        let grey = rgba(0.7, 0.7, 0.7, 1.0);
+
+        let score = playfield.score();
+        let stats = match self.mode {
+            Mode::Sprint => {
+                vec![("Time", ui::format_time(playfield.time())),
+                     ("Lines left", SPRINT_LINES.saturating_sub(score.lines()).to_string())]
+            }
+            Mode::Ultra => {
+                let time_left = match playfield.rules().goal {
+                    Goal::Time(frames) => frames.saturating_sub(playfield.time()),
+                    _ => 0,
+                };
+                vec![("Time left", ui::format_time(time_left)),
+                     ("Score", score.score().to_string()),
+                     ("Lines", score.lines().to_string())]
+            }
+            Mode::Cheese => {
+                vec![("Time", ui::format_time(playfield.time())),
+                     ("Garbage left", playfield.raw().block().garbage_rows().to_string()),
+                     ("Pieces", playfield.pieces().to_string())]
             }
+            Mode::Survival => {
+                let delay = playfield.raw().rising_garbage_delay().unwrap_or(0);
+                vec![("Time", ui::format_time(playfield.time())),
+                     ("Next row", ui::format_time(delay)),
+                     ("Lines", score.lines().to_string())]
+            }
+            Mode::Marathon => {
+                let lines = match playfield.rules().goal {
+                    Goal::Lines(goal) => format!("{}/{}", score.lines(), goal),
+                    _ => score.lines().to_string(),
+                };
+                vec![("Score", score.score().to_string()),
+                     ("Level", playfield.level().level().to_string()),
+                     ("Lines", lines)]
+            }
+            Mode::Versus => {
+                vec![("Lines", score.lines().to_string()),
+                     ("Sent", playfield.garbage_sent().to_string()),
+                     ("Incoming", playfield.garbage_queue().rows().to_string())]
+            }
+            Mode::Endless => {
+                vec![("Score", score.score().to_string()),
+                     ("Level", playfield.level().level().to_string()),
+                     ("Lines", score.lines().to_string())]
+            }
+        };
 
-            _ => {}
+        for (i, &(label, ref value)) in stats.iter().enumerate() {
+            let y = y - 20 - i as i32 * 64;
+            ui::render_text(renderer, x, y, label, 2, grey);
+            ui::render_text(renderer, x, y - 24, value, 3, white);
         }
-
-        None
     }
 
-    fn lock(&mut self) -> Trans<PlayfieldState> {
-        if self.is_falling_block_out_of_bounds() {
-            // NOTE: Partial lock out
-            switch(PlayfieldState::lost())
-        } else {
-            self.lock_falling_block();
-
-            if self.has_lines_to_break() {
-                switch(PlayfieldState::breaking())
-            } else {
-                switch(PlayfieldState::spawn())
+    fn render_game_over(&self, renderer: &mut Renderer, frames: u32, is_new_record: bool, menu: &Menu) {
+        // NOTE: In versus only the losing boards fill up.
+        let rows = (frames / FILL_ROW_FRAMES) as usize;
+        for (player, playfield) in self.playfields().into_iter().enumerate() {
+            if self.opponent.is_none() || playfield.is_lost() {
+                let (x, y) = self.playfield_pos(player);
+                self.playfield_renderer.render_fill(renderer, playfield, x, y, rows);
             }
         }
-    }
+        if frames < self.fill_frames() {
+            return;
+        }
 
-    pub fn update(&mut self, dt: f32, state: &mut PlayfieldState) -> Option<Trans<PlayfieldState>> {
-        //println!("{:?}", state);
+        let (x0, y0, x1, y1) = self.panel_rect();
+        let center_x = (x0 + x1) / 2;
+        let white = rgba(1.0, 1.0, 1.0, 1.0);
+        ui::fill_rect(renderer, x0 + 8, y0 + 8, x1 - 8, y1 - 8, rgba(0.0, 0.0, 0.0, 1.0));
+        renderer.rect(x0 + 8, y0 + 8, x1 - 8, y1 - 8, white);
+
+        let y = y1 - 80;
+        let title = match self.mode {
+            Mode::Versus => self.versus_result(),
+            _ if !self.playfield.is_finished() => "Game over",
+            Mode::Ultra => "Time up",
+            Mode::Marathon => "Complete",
+            _ => "Finished",
+        };
+        ui::render_text_centered(renderer, center_x, y, title, 5, white);
+
+        let mut results = self.results();
+        if is_new_record {
+            results.push("New record".to_string());
+        } else if let Some(best) = self.records.best(self.mode, &self.playfield) {
+            results.push(format!("Best {}", self.format_record(best)));
+        }
+        for (i, line) in results.iter().enumerate() {
+            ui::render_text_centered(renderer, center_x, y - 80 - i as i32 * 32, line, 3, white);
+        }
 
-        match *state {
-            PlayfieldState::Prepare { ref mut countdown } => {
-                countdown.tick(dt);
+        menu.render(renderer, center_x, y0 + 120, 3);
+    }
+}
 
-                println!("coutndown: {:.2}", countdown.elapsed());
+impl Scene for Game {
+    fn handle_event(&mut self, event: &Event) {
+        if let Event::Window {win_event: WindowEvent::FocusLost, ..} = *event {
+            self.pause();
+        }
 
-                if countdown.is_expired() {
-                    return Some(switch(PlayfieldState::spawn()))
-                }
+        // NOTE: In local versus the playfield keys come from each player's
+        // bindings and each controller is a player, otherwise everything
+        // controls player 1.
+        let is_versus = self.opponent.is_some() && !self.is_online();
+        let mut input_events = vec![];
+        for input_event in self.key_bindings.translate(event) {
+            match input_event {
+                InputEvent::Pressed(InputAction::Playfield(_)) |
+                InputEvent::Released(InputAction::Playfield(_)) if is_versus => {}
+                _ => input_events.push((0, input_event)),
             }
+        }
+        if is_versus {
+            for (player, key_bindings) in self.versus_keyboards.iter().enumerate() {
+                input_events.extend(key_bindings.translate(event).into_iter().map(|input_event| (player, input_event)));
+            }
+        }
+        for (slot, input_event) in self.controllers.translate(event) {
+            input_events.push((if is_versus { slot } else { 0 }, input_event));
+        }
 
-            PlayfieldState::Spawn { ref mut spawn_delay } => {
-                assert!(self.falling_block.is_none());
-
-                spawn_delay.tick(dt);
-                if spawn_delay.is_expired() {
-                    self.spawn_falling_block();
-
-                    if self.can_move_falling_block_by(0, -1) {
-                        return Some(switch(PlayfieldState::falling()));
-                    } else {
-                        // NOTE: Block out
-                        return Some(switch(PlayfieldState::lost()));
-                    }
+        // NOTE: A key can be bound to a game action and a menu action, once
+        // the key changed the state the presses are not for the new state,
+        // `Space` resuming the game must not also hard drop.
+        let state = mem::discriminant(self.state_machine.current_state());
+        for (player, input_event) in input_events {
+            if let InputEvent::Pressed(_) = input_event {
+                if mem::discriminant(self.state_machine.current_state()) != state {
+                    continue;
                 }
             }
+            self.handle_input(player, input_event);
+        }
+    }
 
-            PlayfieldState::Falling { ref mut gravity_delay } => {
-                assert!(self.can_move_falling_block_by(0, -1));
-
-                gravity_delay.tick(dt);
-                if gravity_delay.is_expired() {
-                    self.move_falling_block_by(0, -1);
-                    gravity_delay.reset();
-
-                    if !self.can_move_falling_block_by(0, -1) {
-                        return Some(switch(PlayfieldState::locking()));
+    fn update(&mut self, dt: f32) {
+        match *self.state_machine.current_state_mut() {
+            GameState::Running => {
+                self.frame_accumulator += dt;
+                while self.frame_accumulator >= FRAME_TIME {
+                    self.frame_accumulator -= FRAME_TIME;
+
+                    if let Some(ref mut playback) = self.playback {
+                        if playback.is_finished(&self.playfield) {
+                            break;
+                        }
+                        let mut playfields = vec![&mut self.playfield];
+                        playfields.extend(self.opponent.as_mut());
+                        playback.apply(&mut playfields);
                     }
-                }
-            }
 
-            PlayfieldState::Locking {
-                ref mut lock_delay,
-                is_immediately,
-            } => {
-                assert!(self.falling_block.is_some());
-                assert!(!self.can_move_falling_block_by(0, -1));
+                    if let Some(ref mut rollback) = self.rollback {
+                        rollback.tick();
+                        let local_player = rollback.local_player();
+                        self.playfield = rollback.versus().playfield(local_player).clone();
+                        self.opponent = Some(rollback.versus().playfield(1 - local_player).clone());
+                        continue;
+                    }
 
-                if is_immediately {
-                    return Some(self.lock());
+                    match (self.net.as_mut(), self.opponent.as_mut()) {
+                        (Some(net), Some(opponent)) => net.tick(&mut self.playfield, opponent),
+                        (_, opponent) => {
+                            self.playfield.tick();
+
+                            // NOTE: Garbage sent this frame goes to the queue
+                            // of the other board.
+                            if let Some(opponent) = opponent {
+                                opponent.tick();
+                                opponent.receive_garbage(self.playfield.take_outgoing_garbage());
+                                self.playfield.receive_garbage(opponent.take_outgoing_garbage());
+                            }
+                        }
+                    }
                 }
 
-                lock_delay.tick(dt);
-                self.max_lock_delay.tick(dt);
-
-                if lock_delay.is_expired() || self.max_lock_delay.is_expired() {
-                    return Some(self.lock());
+                let is_playback_finished = match self.playback {
+                    Some(ref playback) => playback.is_finished(&self.playfield),
+                    None => false,
+                };
+                let is_opponent_ended = match self.net {
+                    Some(ref net) => net.is_remote_ended(),
+                    None => self.opponent.as_ref().is_some_and(|opponent| opponent.is_ended()),
+                };
+                // NOTE: A rollback game only ends on a confirmed frame.
+                let is_ended = match self.rollback {
+                    Some(ref rollback) => rollback.is_ended() || rollback.is_remote_gone(),
+                    None => self.playfield.is_ended() || is_opponent_ended || is_playback_finished,
+                };
+                if is_ended {
+                    self.save_replay();
+                    let game_over = if self.is_online() {
+                        GameState::online_game_over()
+                    } else {
+                        let is_new_record = self.playback.is_none() && self.records.submit(self.mode, &self.playfield);
+                        GameState::game_over(is_new_record)
+                    };
+                    self.state_machine.trans(switch(game_over));
                 }
             }
 
-            PlayfieldState::Breaking{
-                ref mut breaking_line_delay,
-                ref mut blink_delay,
-            } => {
-                assert!(self.falling_block.is_none());
-                assert!(self.has_lines_to_break());
-
-                breaking_line_delay.tick(dt);
-                if breaking_line_delay.is_expired() {
-                    self.break_lines();
-                    return Some(switch(PlayfieldState::spawn()));
-                }
-
-                blink_delay.tick(dt);
-                if blink_delay.is_expired() {
-                    blink_delay.reset();
-                    self.blink_breaking_lines();
+            GameState::Title {..} |
+            GameState::ModeSelect {..} |
+            GameState::ModeSetup {..} |
+            GameState::Paused {..} |
+            GameState::Settings {..} => {}
+
+            GameState::GameOver {ref mut frames, ..} => {
+                self.frame_accumulator += dt;
+                while self.frame_accumulator >= FRAME_TIME {
+                    self.frame_accumulator -= FRAME_TIME;
+                    *frames += 1;
+
+                    // NOTE: The remote may still miss our last inputs.
+                    if let Some(ref mut rollback) = self.rollback {
+                        rollback.poll();
+                    }
+                    // NOTE: A match server may only decide after our top
+                    // out, once it has played the other board that far.
+                    if let (Some(net), Some(opponent)) = (self.net.as_mut(), self.opponent.as_mut()) {
+                        net.tick(&mut self.playfield, opponent);
+                    }
                 }
             }
-
-            PlayfieldState::Lost => {}
         }
 
-        None
-    }
-}
-
-impl Playfield {
-    pub fn new(width: usize, height: usize, block_size_in_pixels: i32) -> Playfield {
-        Playfield {
-            state_machine: StateMachine::new(PlayfieldState::Prepare {
-                countdown: Timer::new(0.0),
-            }),
-
-            raw: PlayfieldRaw::new(width, height, block_size_in_pixels),
+        // NOTE: Only a game that is on the screen, not the menus around it.
+        let is_playing = matches!(*self.state_machine.current_state(), GameState::Running | GameState::GameOver {..});
+        if let Some(ref mut spectate) = self.spectate {
+            if is_playing {
+                let mut playfields = vec![&self.playfield];
+                playfields.extend(self.opponent.as_ref());
+                spectate.broadcast(&playfields);
+            }
         }
     }
 
-    pub fn handle_event(&mut self, event: &Event) {
-        while let Some(trans) = self.raw.handle_event(event, self.state_machine.current_state_mut()) {
-            self.state_machine.trans(trans);
+    fn render(&self, renderer: &mut Renderer) {
+        match *self.state_machine.current_state() {
+            GameState::Title {ref menu} => self.render_title(renderer, menu),
+            GameState::ModeSelect {ref menu} => self.render_mode_select(renderer, menu),
+            GameState::ModeSetup {mode, ref menu} => self.render_mode_setup(renderer, mode, menu),
+            GameState::Paused {ref menu} => self.render_paused(renderer, "Paused", menu),
+            GameState::Settings {ref menu} => self.render_paused(renderer, "Settings", menu),
+            GameState::GameOver {frames, is_new_record, ref menu} => {
+                self.render_playfields(renderer);
+                self.render_game_over(renderer, frames, is_new_record, menu);
+            }
+            GameState::Running => self.render_playfields(renderer),
         }
     }
+}
 
-    pub fn update(&mut self, dt: f32) {
-        while let Some(trans) = self.raw.update(dt, self.state_machine.current_state_mut()) {
-            self.state_machine.trans(trans);
-        }
+// NOTE: The host is player 1 in a rollback game.
+fn online(mut connection: Connection,
+          seed: u64,
+          local_player: usize,
+          is_rollback: bool,
+          input_config: InputConfig)
+          -> Game {
+    if !is_rollback {
+        return Game::online(connection, seed, input_config);
     }
 
-    pub fn render(&self, renderer: &mut Renderer, x: i32, y: i32, blocks_bitmap: &Bitmap) {
-        self.raw.render(renderer, x, y, blocks_bitmap);
-    }
+    let rules = Mode::Versus.rules(&ModeOptions::new());
+    let session = RollbackSession::start(&mut connection, &rules, seed, local_player);
+    let session = session.unwrap_or_else(|e| panic!("failed to start rollback, {}", e));
+    Game::online_rollback(session, seed, input_config)
 }
 
-
 fn main() {
-    let retris = Game::new();
-    Hammer::new().title("Retris").resolution(800, 800).run(retris);
+    let input_config = InputConfig::load_or_default("./bindings.cfg");
+
+    // NOTE: `retris [seed]` plays every game with the seed, the same seed
+    // always gives the same pieces. `retris --replay <file>` watches a saved
+    // replay. `retris --host [port]` waits for another player to
+    // `retris --join <host:port>` for a versus game over the network, a
+    // match server is joined the same way. With `--rollback` on both sides
+    // the game plays over UDP with rollback instead, not through a match
+    // server. `--spectate <port>` can be added to any of them to stream the
+    // game to spectators on this machine, `--spectate <address:port>` binds
+    // that address instead, like `0.0.0.0:7272` for the whole network.
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let is_rollback = match args.iter().position(|arg| arg == "--rollback") {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    };
+    let spectate = match args.iter().position(|arg| arg == "--spectate") {
+        Some(i) if i + 1 < args.len() => {
+            let address = match args[i + 1].parse::<u16>() {
+                Ok(port) => format!("127.0.0.1:{}", port),
+                Err(_) => args[i + 1].clone(),
+            };
+            args.drain(i..i + 2);
+            Some(SpectatorServer::bind(&address).unwrap_or_else(|e| panic!("failed to spectate, {}", e)))
+        }
+        Some(_) => panic!("usage: retris --spectate <port | address:port>"),
+        None => None,
+    };
+
+    let mut args = args.into_iter();
+    let mut retris = match args.next() {
+        Some(ref arg) if arg == "--replay" => {
+            let path = args.next().expect("usage: retris --replay <file>");
+            let replay = Replay::load(&path).unwrap_or_else(|e| panic!("failed to load replay, {}", e));
+            Game::replay(replay, input_config)
+        }
+        Some(ref arg) if arg == "--host" => {
+            let port = args.next().map_or(DEFAULT_PORT, |port| port.parse::<u16>().expect("port must be a number"));
+            let seed = rand::random::<u64>();
+            println!("waiting for a player on port {}", port);
+            let connection = Connection::host(port, seed).unwrap_or_else(|e| panic!("failed to host, {}", e));
+            online(connection, seed, 0, is_rollback, input_config)
+        }
+        Some(ref arg) if arg == "--join" => {
+            let address = args.next().expect("usage: retris --join <host:port>");
+            let (connection, seed) = Connection::join(&address).unwrap_or_else(|e| panic!("failed to join, {}", e));
+            online(connection, seed, 1, is_rollback, input_config)
+        }
+        arg => {
+            let seed = arg.map(|arg| arg.parse::<u64>().expect("seed must be an unsigned integer"));
+            Game::new(seed, input_config)
+        }
+    };
+    retris.spectate = spectate;
+
+    Hammer::new().title("Retris").resolution(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32).run(retris);
 }
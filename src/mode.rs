@@ -0,0 +1,168 @@
+use level::{LevelGoal, GUIDELINE_GRAVITY_TABLE};
+use randomizer::RandomizerKind;
+use rules::{Goal, RisingGarbage, Rules};
+use timer::FPS;
+
+// NOTE: A game mode is the rules a game is played with and what ends it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Endless,
+    Sprint,
+    Ultra,
+    Marathon,
+    Cheese,
+    Survival,
+    // NOTE: Two players on one machine.
+    Versus,
+}
+
+// NOTE: In the order they are listed in the menu.
+pub const MODES: [Mode; 7] = [
+    Mode::Marathon,
+    Mode::Sprint,
+    Mode::Ultra,
+    Mode::Cheese,
+    Mode::Survival,
+    Mode::Versus,
+    Mode::Endless,
+];
+
+// NOTE: Survival garbage rises faster by this many frames every row, until
+// a row every second.
+pub const SURVIVAL_ACCELERATION: u32 = 3;
+pub const SURVIVAL_MIN_INTERVAL: u32 = FPS;
+
+pub const SPRINT_LINES: u32 = 40;
+
+const MODE_NAMES: [(Mode, &str, &str, &str); 7] = [
+    (Mode::Endless, "endless", "Endless", "Play until you top out"),
+    (Mode::Sprint, "sprint", "Sprint", "Clear 40 lines as fast as you can"),
+    (Mode::Ultra, "ultra", "Ultra", "Score as much as you can in time"),
+    (Mode::Marathon, "marathon", "Marathon", "Clear lines as the levels speed up"),
+    (Mode::Cheese, "cheese", "Cheese", "Dig through the garbage as fast as you can"),
+    (Mode::Survival, "survival", "Survival", "Hold out against rising garbage"),
+    (Mode::Versus, "versus", "Versus", "Two players, clear lines to send garbage"),
+];
+
+// NOTE: The settings a player picks before starting a mode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ModeOptions {
+    pub randomizer: RandomizerKind,
+
+    // NOTE: In frames.
+    pub ultra_time: u32,
+
+    pub start_level: u32,
+    // NOTE: Lines per level.
+    pub level_lines: u32,
+    // NOTE: `None` plays until topping out.
+    pub marathon_lines: Option<u32>,
+
+    pub cheese_rows: usize,
+    // NOTE: In percent, see `Rules::garbage_hole_change`.
+    pub cheese_hole_change: u32,
+
+    // NOTE: Frames between the first garbage rows.
+    pub survival_interval: u32,
+}
+
+impl ModeOptions {
+    pub fn new() -> ModeOptions {
+        ModeOptions {
+            randomizer: RandomizerKind::Bag7,
+
+            ultra_time: 2 * 60 * FPS,
+
+            start_level: 1,
+            level_lines: 10,
+            marathon_lines: Some(150),
+
+            cheese_rows: 10,
+            cheese_hole_change: 100,
+
+            survival_interval: 5 * FPS,
+        }
+    }
+}
+
+impl Default for ModeOptions {
+    fn default() -> ModeOptions {
+        ModeOptions::new()
+    }
+}
+
+impl Mode {
+    // NOTE: Used in files.
+    pub fn name(&self) -> &'static str {
+        MODE_NAMES.iter().find(|&&(mode, ..)| mode == *self).unwrap().1
+    }
+
+    pub fn from_name(name: &str) -> Option<Mode> {
+        MODE_NAMES.iter()
+                  .find(|&&(_, mode_name, ..)| mode_name == name)
+                  .map(|&(mode, ..)| mode)
+    }
+
+    pub fn title(&self) -> &'static str {
+        MODE_NAMES.iter().find(|&&(mode, ..)| mode == *self).unwrap().2
+    }
+
+    pub fn description(&self) -> &'static str {
+        MODE_NAMES.iter().find(|&&(mode, ..)| mode == *self).unwrap().3
+    }
+
+    pub fn player_count(&self) -> usize {
+        match *self {
+            Mode::Versus => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn rules(&self, options: &ModeOptions) -> Rules {
+        let mut rules = match *self {
+            Mode::Endless | Mode::Versus => Rules::new(),
+            Mode::Ultra => {
+                let mut rules = Rules::new();
+                rules.goal = Goal::Time(options.ultra_time);
+                rules
+            }
+            Mode::Marathon => {
+                let mut rules = Rules::new();
+                rules.start_level = options.start_level;
+                rules.level_goal = LevelGoal::Fixed(options.level_lines);
+                rules.goal = match options.marathon_lines {
+                    Some(lines) => Goal::Lines(lines),
+                    None => Goal::None,
+                };
+                rules
+            }
+            Mode::Sprint => {
+                // NOTE: The gravity stays at level 1.
+                let mut rules = Rules::new();
+                rules.gravity_table = vec![GUIDELINE_GRAVITY_TABLE[0]];
+                rules.goal = Goal::Lines(SPRINT_LINES);
+                rules
+            }
+            Mode::Cheese => {
+                let mut rules = Rules::new();
+                rules.gravity_table = vec![GUIDELINE_GRAVITY_TABLE[0]];
+                rules.goal = Goal::Garbage;
+                rules.garbage_rows = options.cheese_rows;
+                rules.garbage_hole_change = options.cheese_hole_change;
+                rules
+            }
+            Mode::Survival => {
+                let mut rules = Rules::new();
+                rules.gravity_table = vec![GUIDELINE_GRAVITY_TABLE[0]];
+                rules.rising_garbage = Some(RisingGarbage {
+                    interval: options.survival_interval,
+                    min_interval: SURVIVAL_MIN_INTERVAL,
+                    acceleration: SURVIVAL_ACCELERATION,
+                });
+                rules
+            }
+        };
+        rules.randomizer = options.randomizer;
+        rules
+    }
+}
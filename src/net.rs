@@ -0,0 +1,710 @@
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use playfield::{Action, Input, Playfield};
+
+// NOTE: Versus over TCP, each side runs its own playfield and is
+// the authority on it. The other side mirrors it: both playfields start
+// from the same seed, so replaying the inputs and the garbage received on
+// the frames they happened gives the same board. The protocol is plain
+// text, one message per line:
+//
+//   retris-net 1
+//   start 42
+//   input 120 p move_left
+//   attack 4
+//   garbage 130 4
+//   frame 131
+//   lost 900
+//   result win
+//   link 50123
+//
+// `attack` is garbage sent to the other side, which answers with `garbage`
+// once it is in its queue so the mirror receives it on the same frame.
+// `frame` tells how far the sender has played, every `input` and `garbage`
+// before it was already sent. `result` only comes from a match server, it
+// plays both playfields itself and decides who won, `win`, `lose` or `draw`
+// when both topped out on the same frame. `link` starts a rollback
+// game instead, see `rollback`, both sides send the port of their UDP socket
+// right after the start and nothing else goes over the connection.
+//
+// Bump `PROTOCOL_VERSION` whenever the messages or the rules change, both
+// sides must play by the same rules to stay in sync.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+pub const DEFAULT_PORT: u16 = 7171;
+
+// NOTE: How long a player that connected has to say hello.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// NOTE: How much may wait to be sent before the other side counts as gone,
+// a minute and more of messages.
+const MAX_PENDING: usize = 64 * 1024;
+
+const MAGIC: &str = "retris-net";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MatchResult {
+    Win,
+    Lose,
+    Draw,
+}
+
+const MATCH_RESULT_NAMES: [(MatchResult, &str); 3] = [
+    (MatchResult::Win, "win"),
+    (MatchResult::Lose, "lose"),
+    (MatchResult::Draw, "draw"),
+];
+
+impl MatchResult {
+    pub fn name(&self) -> &'static str {
+        MATCH_RESULT_NAMES.iter().find(|&&(result, _)| result == *self).unwrap().1
+    }
+
+    pub fn from_name(name: &str) -> Option<MatchResult> {
+        MATCH_RESULT_NAMES.iter()
+                          .find(|&&(_, result_name)| result_name == name)
+                          .map(|&(result, _)| result)
+    }
+
+    // NOTE: The same game seen from the other player.
+    pub fn opposite(&self) -> MatchResult {
+        match *self {
+            MatchResult::Win => MatchResult::Lose,
+            MatchResult::Lose => MatchResult::Win,
+            MatchResult::Draw => MatchResult::Draw,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Hello {
+        version: u32,
+    },
+    // NOTE: Sent by the host after the hello, both sides play this seed.
+    Start {
+        seed: u64,
+    },
+    Input {
+        frame: u32,
+        input: Input,
+    },
+    Attack {
+        rows: u32,
+    },
+    Garbage {
+        frame: u32,
+        rows: u32,
+    },
+    Frame {
+        frame: u32,
+    },
+    Lost {
+        frame: u32,
+    },
+    Result {
+        result: MatchResult,
+    },
+    Link {
+        port: u16,
+    },
+}
+
+fn parse_value<T: ::std::str::FromStr>(key: &str, values: &[&str]) -> Result<T, String> {
+    values.first()
+          .and_then(|value| value.parse::<T>().ok())
+          .ok_or_else(|| format!("bad value for `{}`", key))
+}
+
+impl Message {
+    pub fn format(&self) -> String {
+        match *self {
+            Message::Hello {version} => format!("{} {}", MAGIC, version),
+            Message::Start {seed} => format!("start {}", seed),
+            Message::Input {frame, input} => {
+                let (kind, action) = match input {
+                    Input::Pressed(action) => ("p", action),
+                    Input::Released(action) => ("r", action),
+                };
+                format!("input {} {} {}", frame, kind, action.name())
+            }
+            Message::Attack {rows} => format!("attack {}", rows),
+            Message::Garbage {frame, rows} => format!("garbage {} {}", frame, rows),
+            Message::Frame {frame} => format!("frame {}", frame),
+            Message::Lost {frame} => format!("lost {}", frame),
+            Message::Result {result} => format!("result {}", result.name()),
+            Message::Link {port} => format!("link {}", port),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Message, String> {
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        let (key, values) = match parts.split_first() {
+            Some((key, values)) => (*key, values),
+            None => return Err("empty message".to_string()),
+        };
+
+        let message = match key {
+            MAGIC => Message::Hello {version: parse_value(key, values)?},
+            "start" => Message::Start {seed: parse_value(key, values)?},
+            "input" if values.len() == 3 => {
+                let action = Action::from_name(values[2]).ok_or_else(|| format!("unknown action `{}`", values[2]))?;
+                let input = match values[1] {
+                    "p" => Input::Pressed(action),
+                    "r" => Input::Released(action),
+                    _ => return Err(format!("unknown input `{}`", values[1])),
+                };
+                Message::Input {
+                    frame: parse_value(key, values)?,
+                    input: input,
+                }
+            }
+            "attack" => Message::Attack {rows: parse_value(key, values)?},
+            "garbage" if values.len() == 2 => {
+                Message::Garbage {
+                    frame: parse_value(key, values)?,
+                    rows: parse_value(key, &values[1..])?,
+                }
+            }
+            "frame" => Message::Frame {frame: parse_value(key, values)?},
+            "lost" => Message::Lost {frame: parse_value(key, values)?},
+            "result" => {
+                let result = values.first().and_then(|name| MatchResult::from_name(name));
+                Message::Result {result: result.ok_or_else(|| format!("bad value for `{}`", key))?}
+            }
+            "link" => Message::Link {port: parse_value(key, values)?},
+            _ => return Err(format!("unknown message `{}`", line)),
+        };
+        Ok(message)
+    }
+}
+
+// NOTE: A line based connection, `send` and `receive` never block once the
+// handshake is done. What the stream doesn't take right away waits in
+// `pending`, call `flush` once per loop.
+pub struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    pending: Vec<u8>,
+    is_closed: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Result<Connection, String> {
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        Ok(Connection {
+            stream: stream,
+            buffer: vec![],
+            pending: vec![],
+            is_closed: false,
+        })
+    }
+
+    // NOTE: Waits for a player to join, then sends them the seed.
+    pub fn host(port: u16, seed: u64) -> Result<Connection, String> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|e| format!("port {}: {}", port, e))?;
+        let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+
+        let mut connection = Connection::accept(stream)?;
+        connection.send(&Message::Start {seed: seed})?;
+        Ok(connection)
+    }
+
+    // NOTE: The handshake with a player that connected, the `start` is up
+    // to the caller. Gives up on players that don't say hello in time.
+    pub fn accept(stream: TcpStream) -> Result<Connection, String> {
+        stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(|e| e.to_string())?;
+
+        let mut connection = Connection::new(stream)?;
+        connection.handshake()?;
+        connection.stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+        connection.set_nonblocking()?;
+        Ok(connection)
+    }
+
+    // NOTE: `accept` without waiting, for a server that can't stop for one
+    // player. Only our hello is sent, the first message `receive` gives must
+    // pass `check_hello`.
+    pub fn accept_nonblocking(stream: TcpStream) -> Result<Connection, String> {
+        let mut connection = Connection::new(stream)?;
+        connection.set_nonblocking()?;
+        connection.send(&Message::Hello {version: PROTOCOL_VERSION})?;
+        Ok(connection)
+    }
+
+    // NOTE: Returns the connection and the seed picked by the host.
+    pub fn join(address: &str) -> Result<(Connection, u64), String> {
+        let stream = TcpStream::connect(address).map_err(|e| format!("{}: {}", address, e))?;
+
+        let mut connection = Connection::new(stream)?;
+        connection.handshake()?;
+        let seed = match connection.receive_blocking()? {
+            Message::Start {seed} => seed,
+            message => return Err(format!("expected start, got `{}`", message.format())),
+        };
+        connection.set_nonblocking()?;
+        Ok((connection, seed))
+    }
+
+    fn handshake(&mut self) -> Result<(), String> {
+        self.send(&Message::Hello {version: PROTOCOL_VERSION})?;
+        let message = self.receive_blocking()?;
+        Connection::check_hello(message)
+    }
+
+    pub fn check_hello(message: Message) -> Result<(), String> {
+        match message {
+            Message::Hello {version} if version == PROTOCOL_VERSION => Ok(()),
+            Message::Hello {version} => {
+                Err(format!("unsupported protocol version {}, expected {}", version, PROTOCOL_VERSION))
+            }
+            message => Err(format!("expected hello, got `{}`", message.format())),
+        }
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr, String> {
+        self.stream.peer_addr().map_err(|e| e.to_string())
+    }
+
+    fn set_nonblocking(&mut self) -> Result<(), String> {
+        self.stream.set_nonblocking(true).map_err(|e| e.to_string())
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), String> {
+        self.pending.extend_from_slice(format!("{}\n", message.format()).as_bytes());
+        self.flush()
+    }
+
+    // NOTE: Sends as much of `pending` as the stream takes. Errors once the
+    // other side stopped reading for too long.
+    pub fn flush(&mut self) -> Result<(), String> {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return Err("disconnected".to_string()),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        if self.pending.len() > MAX_PENDING {
+            return Err("not reading".to_string());
+        }
+        Ok(())
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        let end = self.buffer.iter().position(|&byte| byte == b'\n')?;
+        let line = self.buffer.drain(..end + 1).collect::<Vec<_>>();
+        Some(String::from_utf8_lossy(&line).trim().to_string())
+    }
+
+    fn receive_blocking(&mut self) -> Result<Message, String> {
+        loop {
+            if let Some(line) = self.next_line() {
+                return Message::parse(&line);
+            }
+
+            let mut bytes = [0; 1024];
+            match self.stream.read(&mut bytes) {
+                Ok(0) => return Err("disconnected".to_string()),
+                Ok(n) => self.buffer.extend_from_slice(&bytes[..n]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    // NOTE: Every message that arrived since the last call. The messages
+    // sent right before the other side closed still come through, the error
+    // comes after them.
+    pub fn receive(&mut self) -> Result<Vec<Message>, String> {
+        let mut bytes = [0; 1024];
+        while !self.is_closed {
+            match self.stream.read(&mut bytes) {
+                Ok(0) => self.is_closed = true,
+                Ok(n) => self.buffer.extend_from_slice(&bytes[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+
+        let mut messages = vec![];
+        while let Some(line) = self.next_line() {
+            messages.push(Message::parse(&line)?);
+        }
+        if messages.is_empty() && self.is_closed {
+            return Err("disconnected".to_string());
+        }
+        Ok(messages)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum RemoteEvent {
+    Input(Input),
+    Garbage(u32),
+}
+
+// NOTE: Plays a remote playfield from its `input`, `garbage` and `frame`
+// messages.
+pub struct Mirror {
+    // NOTE: The playfield may tick up to this frame.
+    frame: u32,
+    events: VecDeque<(u32, RemoteEvent)>,
+}
+
+impl Mirror {
+    pub fn new() -> Mirror {
+        Mirror {
+            frame: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    // NOTE: Ignores the messages that are not about the playfield, errors
+    // on messages that break the order of the protocol.
+    pub fn handle(&mut self, message: Message) -> Result<(), String> {
+        let (frame, event) = match message {
+            Message::Input {frame, input} => (frame, RemoteEvent::Input(input)),
+            Message::Garbage {frame, rows} => (frame, RemoteEvent::Garbage(rows)),
+            Message::Frame {frame} if frame >= self.frame => {
+                self.frame = frame;
+                return Ok(());
+            }
+            Message::Frame {..} => return Err("frames out of order".to_string()),
+            _ => return Ok(()),
+        };
+
+        if frame < self.frame {
+            return Err(format!("`{}` after frame {}", message.format(), self.frame));
+        }
+        self.events.push_back((frame, event));
+        Ok(())
+    }
+
+    // NOTE: Plays one frame if the remote has played it, returns the
+    // garbage the playfield sent on it.
+    pub fn advance_frame(&mut self, playfield: &mut Playfield) -> Option<u32> {
+        if playfield.frame() >= self.frame {
+            return None;
+        }
+        while let Some(&(frame, event)) = self.events.front() {
+            if frame > playfield.frame() {
+                break;
+            }
+            match event {
+                RemoteEvent::Input(input) => playfield.handle_input(input),
+                RemoteEvent::Garbage(garbage) => playfield.receive_garbage(garbage),
+            }
+            self.events.pop_front();
+        }
+        playfield.tick();
+        Some(playfield.take_outgoing_garbage())
+    }
+
+    // NOTE: Plays as far as the remote has played, returns the garbage the
+    // playfield sent on the way.
+    pub fn advance(&mut self, playfield: &mut Playfield) -> u32 {
+        let mut rows = 0;
+        while let Some(sent) = self.advance_frame(playfield) {
+            rows += sent;
+        }
+        rows
+    }
+}
+
+impl Default for Mirror {
+    fn default() -> Mirror {
+        Mirror::new()
+    }
+}
+
+// NOTE: One side of a network game. Drives the local playfield, and the
+// mirror of the remote one as far as the remote has played.
+pub struct NetSession {
+    connection: Connection,
+    mirror: Mirror,
+    is_lost_sent: bool,
+    is_remote_lost: bool,
+    // NOTE: Set by a match server when the game is decided.
+    result: Option<MatchResult>,
+    // NOTE: Why the connection dropped.
+    error: Option<String>,
+}
+
+impl NetSession {
+    pub fn new(connection: Connection) -> NetSession {
+        NetSession {
+            connection: connection,
+            mirror: Mirror::new(),
+            is_lost_sent: false,
+            is_remote_lost: false,
+            result: None,
+            error: None,
+        }
+    }
+
+    pub fn is_remote_lost(&self) -> bool {
+        self.is_remote_lost
+    }
+
+    // NOTE: Only set by a match server.
+    pub fn result(&self) -> Option<MatchResult> {
+        self.result
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    // NOTE: The remote player lost or left, or the server ended the game.
+    pub fn is_remote_ended(&self) -> bool {
+        self.is_remote_lost || self.result.is_some() || self.error.is_some()
+    }
+
+    fn send(&mut self, message: Message) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.connection.send(&message) {
+            self.error = Some(e);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.connection.flush() {
+            self.error = Some(e);
+        }
+    }
+
+    // NOTE: Use instead of `Playfield::handle_input` for the local player.
+    pub fn handle_input(&mut self, local: &mut Playfield, input: Input) {
+        self.send(Message::Input {
+            frame: local.frame(),
+            input: input,
+        });
+        local.handle_input(input);
+    }
+
+    fn receive(&mut self, local: &mut Playfield) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let messages = match self.connection.receive() {
+            Ok(messages) => messages,
+            Err(e) => {
+                self.error = Some(e);
+                return;
+            }
+        };
+
+        for message in messages {
+            match message {
+                Message::Lost {..} => self.is_remote_lost = true,
+                Message::Result {result} => self.result = Some(result),
+                Message::Attack {rows} => {
+                    if !local.is_ended() {
+                        local.receive_garbage(rows);
+                        self.send(Message::Garbage {
+                            frame: local.frame(),
+                            rows: rows,
+                        });
+                    }
+                }
+                message => {
+                    if let Err(e) = self.mirror.handle(message) {
+                        self.error = Some(e);
+                    }
+                }
+            }
+        }
+    }
+
+    // NOTE: Use instead of `Playfield::tick`, once per frame.
+    pub fn tick(&mut self, local: &mut Playfield, remote: &mut Playfield) {
+        self.receive(local);
+
+        if !local.is_ended() {
+            local.tick();
+
+            let rows = local.take_outgoing_garbage();
+            if rows > 0 {
+                self.send(Message::Attack {rows: rows});
+            }
+            let frame = local.frame();
+            self.send(Message::Frame {frame: frame});
+        }
+
+        if local.is_lost() && !self.is_lost_sent {
+            self.is_lost_sent = true;
+            let frame = local.frame();
+            self.send(Message::Lost {frame: frame});
+        }
+        // NOTE: Whatever didn't fit in the stream this frame.
+        self.flush();
+
+        // NOTE: The garbage of the mirror is sent by the remote itself.
+        self.mirror.advance(remote);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng::Rng;
+    use rules::Rules;
+
+    // NOTE: What the other side gets, through the text of the line.
+    fn send(message: Message) -> Message {
+        Message::parse(&message.format()).unwrap()
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        let messages = [
+            Message::Hello {version: PROTOCOL_VERSION},
+            Message::Start {seed: u64::MAX},
+            Message::Input {frame: 120, input: Input::Pressed(Action::MoveLeft)},
+            Message::Input {frame: 0, input: Input::Released(Action::Rotate180)},
+            Message::Attack {rows: 4},
+            Message::Garbage {frame: 130, rows: 4},
+            Message::Frame {frame: 131},
+            Message::Lost {frame: 900},
+            Message::Result {result: MatchResult::Win},
+            Message::Result {result: MatchResult::Lose},
+            Message::Result {result: MatchResult::Draw},
+            Message::Link {port: 50123},
+        ];
+        for &message in &messages {
+            assert_eq!(send(message), message);
+        }
+        assert_eq!(Message::parse("  input 3   p   hold ").unwrap(),
+                   Message::Input {frame: 3, input: Input::Pressed(Action::Hold)});
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let error = |line: &str| Message::parse(line).unwrap_err();
+        assert_eq!(error(""), "empty message");
+        assert_eq!(error("warp 5"), "unknown message `warp 5`");
+        assert_eq!(error("retris-net one"), "bad value for `retris-net`");
+        assert_eq!(error("start -1"), "bad value for `start`");
+        assert_eq!(error("input 1 p"), "unknown message `input 1 p`");
+        assert_eq!(error("input x p hold"), "bad value for `input`");
+        assert_eq!(error("input 1 q hold"), "unknown input `q`");
+        assert_eq!(error("input 1 p jump"), "unknown action `jump`");
+        assert_eq!(error("garbage 4"), "unknown message `garbage 4`");
+        assert_eq!(error("result tie"), "bad value for `result`");
+        assert_eq!(error("link 70000"), "bad value for `link`");
+    }
+
+    #[test]
+    fn hello_checks_the_version() {
+        assert!(Connection::check_hello(Message::Hello {version: PROTOCOL_VERSION}).is_ok());
+        assert_eq!(Connection::check_hello(Message::Hello {version: PROTOCOL_VERSION + 1}).unwrap_err(),
+                   format!("unsupported protocol version {}, expected {}", PROTOCOL_VERSION + 1, PROTOCOL_VERSION));
+        assert_eq!(Connection::check_hello(Message::Start {seed: 1}).unwrap_err(), "expected hello, got `start 1`");
+    }
+
+    #[test]
+    fn mirror_plays_the_same_board() {
+        const ACTIONS: [Action; 6] = [
+            Action::MoveLeft,
+            Action::MoveRight,
+            Action::RotateClockwise,
+            Action::HardDrop,
+            Action::Hold,
+            Action::SoftDrop,
+        ];
+
+        let rules = Rules::new();
+        let mut local = Playfield::new(&rules, 9);
+        let mut remote = Playfield::new(&rules, 9);
+        let mut mirror = Mirror::new();
+        let mut rng = Rng::new(4);
+        let mut messages = vec![];
+        let mut sent = 0;
+        let mut mirrored = 0;
+
+        while local.frame() < 3000 && !local.is_ended() {
+            if rng.gen_range(6) == 0 {
+                let action = ACTIONS[rng.gen_range(ACTIONS.len())];
+                let input = if rng.gen_range(2) == 0 { Input::Pressed(action) } else { Input::Released(action) };
+                messages.push(Message::Input {frame: local.frame(), input: input});
+                local.handle_input(input);
+            }
+            // NOTE: Garbage from the other side now and then.
+            if rng.gen_range(200) == 0 {
+                local.receive_garbage(2);
+                messages.push(Message::Garbage {frame: local.frame(), rows: 2});
+            }
+            local.tick();
+            sent += local.take_outgoing_garbage();
+            messages.push(Message::Frame {frame: local.frame()});
+
+            // NOTE: The messages arrive in bursts.
+            if rng.gen_range(10) == 0 {
+                for message in messages.drain(..) {
+                    mirror.handle(send(message)).unwrap();
+                }
+                mirrored += mirror.advance(&mut remote);
+                assert!(remote.frame() <= local.frame());
+            }
+        }
+        for message in messages.drain(..) {
+            mirror.handle(send(message)).unwrap();
+        }
+        mirrored += mirror.advance(&mut remote);
+
+        assert!(local.pieces() > 10);
+        assert_eq!(remote.frame(), local.frame());
+        assert_eq!(remote.score().score(), local.score().score());
+        assert_eq!(remote.pieces(), local.pieces());
+        assert_eq!(mirrored, sent);
+        let board = |playfield: &Playfield| {
+            playfield.raw().block().data.iter().map(|cell| cell.map(|cell| cell.index)).collect::<Vec<_>>()
+        };
+        assert_eq!(board(&remote), board(&local));
+    }
+
+    #[test]
+    fn mirror_rejects_messages_out_of_order() {
+        let mut mirror = Mirror::new();
+        mirror.handle(Message::Frame {frame: 10}).unwrap();
+        assert_eq!(mirror.handle(Message::Frame {frame: 9}).unwrap_err(), "frames out of order");
+        assert_eq!(mirror.handle(Message::Input {frame: 5, input: Input::Pressed(Action::Hold)}).unwrap_err(),
+                   "`input 5 p hold` after frame 10");
+        assert!(mirror.handle(Message::Garbage {frame: 10, rows: 1}).is_ok());
+        assert!(mirror.handle(Message::Result {result: MatchResult::Win}).is_ok());
+    }
+
+    #[test]
+    fn send_never_blocks_and_gives_up_on_a_peer_not_reading() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        // NOTE: Connected but never reads.
+        let _peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut connection = Connection::accept_nonblocking(listener.accept().unwrap().0).unwrap();
+
+        let mut frame = 0;
+        let error = loop {
+            if let Err(e) = connection.send(&Message::Frame {frame: frame}) {
+                break e;
+            }
+            frame += 1;
+            assert!(frame < 10_000_000, "the backlog never filled up");
+        };
+        assert_eq!(error, "not reading");
+    }
+}
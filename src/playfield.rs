@@ -0,0 +1,1181 @@
+use block::*;
+use garbage::{self, GarbageQueue};
+use handling::{AutoShift, Handling};
+use level::{Level, G, INSTANT_GRAVITY};
+use rng::Rng;
+use rules::{Goal, Rules};
+use score::{LineClear, Score, TSpin};
+use timer::Timer;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateClockwise,
+    RotateCounterClockwise,
+    Rotate180,
+    Hold,
+}
+
+const ACTION_NAMES: [(Action, &str); 8] = [
+    (Action::MoveLeft, "move_left"),
+    (Action::MoveRight, "move_right"),
+    (Action::SoftDrop, "soft_drop"),
+    (Action::HardDrop, "hard_drop"),
+    (Action::RotateClockwise, "rotate_cw"),
+    (Action::RotateCounterClockwise, "rotate_ccw"),
+    (Action::Rotate180, "rotate_180"),
+    (Action::Hold, "hold"),
+];
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        ACTION_NAMES.iter().find(|&&(action, _)| action == *self).unwrap().1
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTION_NAMES.iter()
+                    .find(|&&(_, action_name)| action_name == name)
+                    .map(|&(action, _)| action)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Input {
+    Pressed(Action),
+    Released(Action),
+}
+
+#[derive(Clone, Debug)]
+pub enum PlayfieldState {
+    Prepare {
+        countdown: Timer,
+    },
+    Spawn {
+        spawn_delay: Timer,
+    },
+    Falling {
+        // NOTE: Accumulated gravity, one cell is dropped for every `G`.
+        gravity_accumulator: u32,
+    },
+    Locking {
+        lock_delay: Timer,
+        is_immediately: bool,
+    },
+    Breaking {
+        breaking_line_delay: Timer,
+        blink_delay: Timer,
+    },
+    Lost,
+    // NOTE: The goal of the rules was reached.
+    Finished,
+}
+
+impl PlayfieldState {
+    pub fn prepare() -> PlayfieldState {
+        PlayfieldState::Prepare {
+            countdown: Timer::new(0),
+        }
+    }
+
+    pub fn spawn() -> PlayfieldState {
+        PlayfieldState::Spawn {
+            spawn_delay: Timer::new(0),
+        }
+    }
+
+    pub fn falling() -> PlayfieldState {
+        PlayfieldState::Falling {
+            gravity_accumulator: 0,
+        }
+    }
+
+    pub fn locking() -> PlayfieldState {
+        PlayfieldState::Locking {
+            lock_delay: Timer::new(30),
+            is_immediately: false,
+        }
+    }
+
+    pub fn locking_immediately() -> PlayfieldState {
+        PlayfieldState::Locking {
+            lock_delay: Timer::new(0),
+            is_immediately: true,
+        }
+    }
+
+    pub fn breaking() -> PlayfieldState {
+        PlayfieldState::Breaking {
+            breaking_line_delay: Timer::new(15),
+            blink_delay: Timer::new(5),
+        }
+    }
+
+    pub fn lost() -> PlayfieldState {
+        PlayfieldState::Lost
+    }
+
+    pub fn finished() -> PlayfieldState {
+        PlayfieldState::Finished
+    }
+}
+
+#[derive(Clone)]
+pub struct Playfield {
+    state: PlayfieldState,
+    raw: PlayfieldRaw,
+
+    // NOTE: How many times `tick` was called.
+    frame: u32,
+}
+
+// NOTE: Mixed into the seed of the garbage rng.
+const GARBAGE_SEED: u64 = 0x6761_7262_6167_6500;
+
+#[derive(Clone)]
+pub struct PlayfieldRaw {
+    // NOTE: Kept to start over with `reset`.
+    rules: Rules,
+
+    block: Block,
+
+    falling_block: Option<FallingBlock>,
+
+    rng: Rng,
+    generator: BlockTemplateGenerator,
+    block_template: BlockTemplate,
+
+    // NOTE: Garbage has its own rng so it doesn't change the pieces.
+    garbage_rng: Rng,
+    garbage_hole: usize,
+    // NOTE: Frames until the next rising garbage row, and the current
+    // interval between rows.
+    rising_garbage_delay: u32,
+    rising_garbage_interval: u32,
+
+    // NOTE: Garbage sent by line clears, taken by whoever delivers it to
+    // the opponent, and garbage received that enters at a spawn once its
+    // delay is over.
+    outgoing_garbage: u32,
+    garbage_sent: u32,
+    garbage_queue: GarbageQueue,
+
+    held_template: Option<BlockTemplateRef>,
+    can_hold_falling_block: bool,
+
+    // NOTE: For T-spin detection, the kick used by the last rotation if the
+    // last successful move of the falling block was a rotation.
+    last_rotation_kick: Option<usize>,
+    // NOTE: The kick is into `ROTATE_180_WALL_KICK_TABLE`, not the SRS one.
+    is_last_rotation_180: bool,
+
+    handling: Handling,
+    auto_shift: AutoShift,
+    is_soft_drop_held: bool,
+
+    max_lock_delay: Timer,
+
+    score: Score,
+    level: Level,
+
+    // NOTE: Frames since the first spawn, stops when the game ends or the
+    // goal is reached.
+    time: u32,
+    // NOTE: Pieces locked so far.
+    pieces: u32,
+
+    breaking_lines: Vec<usize>,
+    is_breaking_lines_visible: bool,
+}
+
+impl PlayfieldRaw {
+    pub fn new(rules: &Rules, seed: u64) -> PlayfieldRaw {
+        let block_template = BlockTemplate::new();
+        let mut rng = Rng::new(seed);
+        let randomizer = rules.randomizer.create(block_template.shape_count());
+        let generator = BlockTemplateGenerator::new(&block_template, randomizer, &mut rng);
+        let mut garbage_rng = Rng::new(seed ^ GARBAGE_SEED);
+        let garbage_hole = garbage_rng.gen_range(rules.width);
+        let mut raw = PlayfieldRaw {
+            rules: rules.clone(),
+
+            block: Block::new(rules.width, rules.height),
+
+            falling_block: None,
+
+            rng: rng,
+            generator: generator,
+            block_template: block_template,
+
+            garbage_rng: garbage_rng,
+            garbage_hole: garbage_hole,
+            rising_garbage_delay: rules.rising_garbage.map_or(0, |rising| rising.interval),
+            rising_garbage_interval: rules.rising_garbage.map_or(0, |rising| rising.interval),
+
+            outgoing_garbage: 0,
+            garbage_sent: 0,
+            garbage_queue: GarbageQueue::new(),
+
+            held_template: None,
+            can_hold_falling_block: true,
+
+            last_rotation_kick: None,
+            is_last_rotation_180: false,
+
+            handling: rules.handling,
+            auto_shift: AutoShift::new(rules.handling),
+            is_soft_drop_held: false,
+
+            max_lock_delay: Timer::new(60),
+
+            score: Score::new(),
+            level: Level::new(rules.start_level, rules.level_goal, rules.gravity_table.clone()),
+
+            time: 0,
+            pieces: 0,
+
+            breaking_lines: vec![],
+            is_breaking_lines_visible: true,
+        };
+
+        raw.add_garbage_rows(rules.garbage_rows);
+        raw
+    }
+
+    // NOTE: Starts over with the same rules and a new seed, as if created
+    // by `new`.
+    pub fn reset(&mut self, seed: u64) {
+        *self = PlayfieldRaw::new(&self.rules, seed);
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    pub fn block(&self) -> &Block {
+        &self.block
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.rng.seed()
+    }
+
+    pub fn block_template(&self) -> &BlockTemplate {
+        &self.block_template
+    }
+
+    pub fn falling_block(&self) -> Option<&FallingBlock> {
+        self.falling_block.as_ref()
+    }
+
+    pub fn ghost_block_pos(&self) -> Option<(i32, i32)> {
+        self.falling_block.as_ref().map(|falling_block| {
+            self.block.get_ghost_block_pos(falling_block.x,
+                                           falling_block.y,
+                                           self.block_template.block(&falling_block.template))
+        })
+    }
+
+    pub fn held_template(&self) -> Option<&BlockTemplateRef> {
+        self.held_template.as_ref()
+    }
+
+    pub fn next_templates(&self) -> &[BlockTemplateRef] {
+        self.generator.next_templates()
+    }
+
+    pub fn score(&self) -> &Score {
+        &self.score
+    }
+
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+
+    pub fn time(&self) -> u32 {
+        self.time
+    }
+
+    pub fn pieces(&self) -> u32 {
+        self.pieces
+    }
+
+    pub fn is_goal_reached(&self) -> bool {
+        match self.rules.goal {
+            Goal::None => false,
+            Goal::Lines(lines) => self.score.lines() >= lines,
+            Goal::Time(frames) => self.time >= frames,
+            Goal::Garbage => self.block.garbage_rows() == 0,
+        }
+    }
+
+    fn next_garbage_hole(&mut self) -> usize {
+        let width = self.block.width;
+        if width > 1 && (self.garbage_rng.gen_range(100) as u32) < self.rules.garbage_hole_change {
+            self.garbage_hole = (self.garbage_hole + 1 + self.garbage_rng.gen_range(width - 1)) % width;
+        }
+        self.garbage_hole
+    }
+
+    // NOTE: Pushes the stack up by `rows` garbage rows. Returns true if the
+    // stack was pushed out of the top.
+    pub fn add_garbage_rows(&mut self, rows: usize) -> bool {
+        let holes = (0..rows).map(|_| self.next_garbage_hole()).collect::<Vec<_>>();
+        self.block.insert_garbage_rows(&holes, GARBAGE_CELL)
+    }
+
+    // NOTE: Like `add_garbage_rows`, but the rows share one hole, the rows
+    // of an attack come in as one clean column.
+    fn add_garbage_batch(&mut self, rows: usize) -> bool {
+        let hole = self.next_garbage_hole();
+        self.block.insert_garbage_rows(&vec![hole; rows], GARBAGE_CELL)
+    }
+
+    // NOTE: Garbage waits while lines are breaking, the rows to break would
+    // move.
+    pub fn can_insert_garbage(&self, state: &PlayfieldState) -> bool {
+        matches!(*state,
+                 PlayfieldState::Spawn {..} |
+                 PlayfieldState::Falling {..} |
+                 PlayfieldState::Locking {..})
+    }
+
+    // NOTE: Garbage pushes the stack up, if it pushes into the falling block
+    // the block is pushed up as well. The rows are one batch with one hole.
+    pub fn insert_garbage(&mut self, rows: usize, state: &mut PlayfieldState) -> Option<PlayfieldState> {
+        assert!(self.can_insert_garbage(state));
+
+        if rows == 0 {
+            return None;
+        }
+
+        if self.add_garbage_batch(rows) {
+            // NOTE: Top out
+            return Some(PlayfieldState::lost());
+        }
+
+        // NOTE: Everything moved up by `rows`, so the block fits again
+        // after at most `rows` rows.
+        let is_pushed = self.falling_block.is_some() && !self.can_move_falling_block_by(0, 0);
+        if is_pushed {
+            let dy = (1..rows as i32 + 1).find(|&dy| self.can_move_falling_block_by(0, dy)).unwrap_or(rows as i32);
+            if let Some(ref mut falling_block) = self.falling_block {
+                falling_block.move_by(0, dy);
+            }
+            self.last_rotation_kick = None;
+        }
+
+        match *state {
+            PlayfieldState::Falling {..} if !self.can_move_falling_block_by(0, -1) => {
+                Some(PlayfieldState::locking())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn rising_garbage_delay(&self) -> Option<u32> {
+        self.rules.rising_garbage.map(|_| self.rising_garbage_delay)
+    }
+
+    fn rise_garbage(&mut self, state: &mut PlayfieldState) -> Option<PlayfieldState> {
+        let rising = self.rules.rising_garbage?;
+
+        if self.rising_garbage_delay > 0 {
+            self.rising_garbage_delay -= 1;
+        }
+        if self.rising_garbage_delay > 0 || !self.can_insert_garbage(state) {
+            return None;
+        }
+
+        self.rising_garbage_interval = self.rising_garbage_interval
+                                           .saturating_sub(rising.acceleration)
+                                           .max(rising.min_interval);
+        self.rising_garbage_delay = self.rising_garbage_interval;
+        self.insert_garbage(1, state)
+    }
+
+    // NOTE: At 20G the block never floats, it sits on the stack as soon
+    // as it spawns or moves.
+    fn is_instant_gravity(&self) -> bool {
+        self.level.gravity() >= INSTANT_GRAVITY
+    }
+
+    fn is_sonic_drop(&self) -> bool {
+        self.is_soft_drop_held && self.handling.soft_drop_factor == 0
+    }
+
+    fn gravity(&self) -> u32 {
+        if self.is_soft_drop_held {
+            self.level.gravity().saturating_mul(self.handling.soft_drop_factor)
+        } else {
+            self.level.gravity()
+        }
+    }
+
+    pub fn breaking_lines(&self) -> &[usize] {
+        &self.breaking_lines
+    }
+
+    pub fn is_breaking_lines_visible(&self) -> bool {
+        self.is_breaking_lines_visible
+    }
+
+    fn spawn_falling_block(&mut self) {
+        let template = self.generator.generate(&self.block_template, &mut self.rng);
+        self.spawn_falling_block_with(template);
+    }
+
+    fn spawn_falling_block_with(&mut self, template: BlockTemplateRef) {
+        let bottom = self.block_template.block(&template).bottom();
+        self.falling_block = Some(FallingBlock::new(3, self.block.height as i32 - bottom as i32, template));
+        self.last_rotation_kick = None;
+        self.auto_shift.cut();
+    }
+
+    // NOTE: Returns how many cells the block dropped.
+    pub fn drop_falling_block(&mut self) -> u32 {
+        if let Some(ref mut falling_block) = self.falling_block {
+            let (x, y) = self.block.get_ghost_block_pos(falling_block.x,
+                                                        falling_block.y,
+                                                        self.block_template.block(&falling_block.template));
+            let distance = (falling_block.y - y) as u32;
+            if distance > 0 {
+                self.last_rotation_kick = None;
+            }
+            falling_block.move_to(x, y);
+            distance
+        } else {
+            0
+        }
+    }
+
+    pub fn hold_falling_block(&mut self) {
+        if self.can_hold_falling_block {
+            let falling_block = self.falling_block.take();
+            if let Some(falling_block) = falling_block {
+                let mut template = falling_block.template;
+                template.order = 0;
+                if let Some(held_template) = self.held_template.take() {
+                    self.spawn_falling_block_with(held_template);
+                } else {
+                    self.spawn_falling_block();
+                }
+                self.held_template = Some(template);
+                self.can_hold_falling_block = false;
+            }
+        }
+    }
+
+    // NOTE: direction is 1 for clockwise, -1 for counter clockwise and 2
+    // for 180 degrees.
+    pub fn rotate_falling_block(&mut self, direction: i32) {
+        if let Some(ref mut falling_block) = self.falling_block {
+            let mut new_template = falling_block.template;
+            let table: &[(i32, i32)] = if direction == 2 {
+                new_template.rrotate();
+                new_template.rrotate();
+                &ROTATE_180_WALL_KICK_TABLE
+            } else {
+                if direction > 0 {
+                    new_template.rrotate();
+                } else {
+                    new_template.lrotate();
+                }
+                self.block_template.wall_kick_table(&falling_block.template, &new_template)
+            };
+
+            for (kick, &(dx, dy)) in table.iter().enumerate() {
+                if self.block.is_valid_position(falling_block.x + dx,
+                                                falling_block.y + dy,
+                                                self.block_template
+                                                    .block(&new_template)) {
+                    falling_block.move_by(dx, dy);
+                    falling_block.template = new_template;
+                    self.last_rotation_kick = Some(kick);
+                    self.is_last_rotation_180 = direction == 2;
+                    self.auto_shift.cut();
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn can_move_falling_block_by(&self, dx: i32, dy: i32) -> bool {
+        if let Some(ref falling_block) = self.falling_block {
+            self.block.is_valid_position(falling_block.x + dx,
+                                         falling_block.y + dy,
+                                         self.block_template
+                                             .block(&falling_block.template))
+        } else {
+            false
+        }
+    }
+
+    pub fn move_falling_block_by(&mut self, dx: i32, dy: i32) -> bool {
+        if self.can_move_falling_block_by(dx, dy) {
+            self.falling_block.as_mut().unwrap().move_by(dx, dy);
+            self.last_rotation_kick = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_falling_block_out_of_bounds(&self) -> bool {
+        if let Some(ref falling_block) = self.falling_block {
+            self.block.is_out_of_bounds(falling_block.x,
+                                        falling_block.y,
+                                        self.block_template.block(&falling_block.template))
+        } else {
+            true
+        }
+    }
+
+    fn is_occupied(&self, x: i32, y: i32) -> bool {
+        x < 0 || x >= self.block.width as i32 || y < 0 ||
+        self.block.get(x as usize, y as usize).is_some()
+    }
+
+    // NOTE: 3-corner T-spin detection, see https://tetris.wiki/T-Spin.
+    // A T-spin whose two front corners are not both filled is a mini,
+    // unless the rotation used the last kick of the SRS table.
+    fn detect_t_spin(&self) -> TSpin {
+        let falling_block = match self.falling_block {
+            Some(ref falling_block) => falling_block,
+            None => return TSpin::None,
+        };
+
+        let kick = match self.last_rotation_kick {
+            Some(kick) => kick,
+            None => return TSpin::None,
+        };
+
+        if falling_block.template.shape != SHAPE_T {
+            return TSpin::None;
+        }
+
+        // Corners of the 3x3 T box, relative to its origin.
+        let corner = |dx: i32, dy: i32| self.is_occupied(falling_block.x + dx, falling_block.y + dy);
+        let corners = [corner(0, 0), corner(2, 0), corner(0, 2), corner(2, 2)];
+        if corners.iter().filter(|&&occupied| occupied).count() < 3 {
+            return TSpin::None;
+        }
+
+        let (front_a, front_b) = match falling_block.template.order {
+            // Pointing up
+            0 => (corner(0, 2), corner(2, 2)),
+            // Pointing right
+            1 => (corner(2, 2), corner(2, 0)),
+            // Pointing down
+            2 => (corner(0, 0), corner(2, 0)),
+            // Pointing left
+            _ => (corner(0, 0), corner(0, 2)),
+        };
+
+        let is_last_kick = kick == 4 && !self.is_last_rotation_180;
+        if (front_a && front_b) || is_last_kick {
+            TSpin::Full
+        } else {
+            TSpin::Mini
+        }
+    }
+
+    pub fn lock_falling_block(&mut self) {
+        assert!(!self.is_falling_block_out_of_bounds());
+        let falling_block = self.falling_block.take();
+        if let Some(falling_block) = falling_block {
+            self.block.set_with_block(falling_block.x,
+                                      falling_block.y,
+                                      self.block_template.block(&falling_block.template));
+            self.can_hold_falling_block = true;
+            self.max_lock_delay.reset();
+            self.pieces += 1;
+        }
+    }
+
+    // NOTE: Call after `has_lines_to_break`, true if the lines to break are
+    // all that is left.
+    fn is_perfect_clear(&self) -> bool {
+        let block = &self.block;
+        !self.breaking_lines.is_empty() && (0..block.height).all(|row| {
+            self.breaking_lines.contains(&row) || (0..block.width).all(|col| block.get(col, row).is_none())
+        })
+    }
+
+    pub fn has_lines_to_break(&mut self) -> bool {
+        self.breaking_lines = self.block.get_break_lines();
+        !self.breaking_lines.is_empty()
+    }
+
+    pub fn blink_breaking_lines(&mut self) {
+        self.is_breaking_lines_visible = !self.is_breaking_lines_visible;
+    }
+
+    pub fn break_lines(&mut self) {
+        self.block.break_lines();
+        self.breaking_lines.clear();
+        self.is_breaking_lines_visible = true;
+    }
+
+    fn handle_common_action(&mut self, action: Action) {
+        match action {
+            Action::RotateClockwise => {
+                self.rotate_falling_block(1);
+            }
+            Action::RotateCounterClockwise => {
+                self.rotate_falling_block(-1);
+            }
+            Action::Rotate180 => {
+                self.rotate_falling_block(2);
+            }
+            Action::MoveLeft => {
+                self.move_falling_block_by(-1, 0);
+            }
+            Action::MoveRight => {
+                self.move_falling_block_by(1, 0);
+            }
+            Action::Hold => {
+                self.hold_falling_block();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_action(&mut self, action: Action, state: &mut PlayfieldState) -> Option<PlayfieldState> {
+        match *state {
+            PlayfieldState::Falling { ref mut gravity_accumulator } => {
+                match action {
+                    Action::SoftDrop => {
+                        if self.move_falling_block_by(0, -1) {
+                            self.score.award_soft_drop(1);
+                        }
+                        *gravity_accumulator = 0;
+                    }
+                    Action::HardDrop => {
+                        let distance = self.drop_falling_block();
+                        self.score.award_hard_drop(distance);
+                        return Some(PlayfieldState::locking_immediately());
+                    }
+                    _ => {
+                        self.handle_common_action(action);
+                    }
+                }
+
+                if self.is_instant_gravity() {
+                    self.drop_falling_block();
+                }
+
+                if !self.can_move_falling_block_by(0, -1) {
+                    return Some(PlayfieldState::locking());
+                }
+            }
+
+            PlayfieldState::Locking { .. } => {
+                // NOTE: A landed block (every block at instant gravity) still
+                // hard drops.
+                if action == Action::HardDrop {
+                    let distance = self.drop_falling_block();
+                    self.score.award_hard_drop(distance);
+                    return Some(PlayfieldState::locking_immediately());
+                }
+
+                self.handle_common_action(action);
+
+                if self.can_move_falling_block_by(0, -1) {
+                    if self.is_instant_gravity() {
+                        // NOTE: Moved off a ledge, lands on the lower surface
+                        // right away with a fresh lock delay.
+                        self.drop_falling_block();
+                        return Some(PlayfieldState::locking());
+                    }
+
+                    return Some(PlayfieldState::falling());
+                }
+            }
+
+            _ => {}
+        }
+
+        None
+    }
+
+    fn lock(&mut self) -> PlayfieldState {
+        if self.is_falling_block_out_of_bounds() {
+            // NOTE: Partial lock out
+            PlayfieldState::lost()
+        } else {
+            let t_spin = self.detect_t_spin();
+            self.lock_falling_block();
+
+            let has_lines_to_break = self.has_lines_to_break();
+            let clear = LineClear {
+                lines: self.breaking_lines.len(),
+                t_spin: t_spin,
+            };
+            let is_back_to_back = clear.is_difficult() && self.score.is_back_to_back();
+            self.score.award_lock(clear, self.level.level());
+
+            // NOTE: The attack cancels incoming garbage first.
+            let attack = garbage::attack(&clear, self.score.combo(), is_back_to_back, self.is_perfect_clear());
+            let attack = self.garbage_queue.cancel(attack);
+            self.outgoing_garbage += attack;
+            self.garbage_sent += attack;
+            self.level.advance(&clear, is_back_to_back);
+
+            if has_lines_to_break {
+                PlayfieldState::breaking()
+            } else {
+                PlayfieldState::spawn()
+            }
+        }
+    }
+
+    pub fn update(&mut self, state: &mut PlayfieldState) -> Option<PlayfieldState> {
+        //println!("{:?}", state);
+
+        match *state {
+            PlayfieldState::Prepare { ref mut countdown } => {
+                countdown.tick();
+
+                if countdown.is_expired() {
+                    return Some(PlayfieldState::spawn())
+                }
+            }
+
+            PlayfieldState::Spawn { ref mut spawn_delay } => {
+                assert!(self.falling_block.is_none());
+
+                // NOTE: Finish once the lines of the last clear are gone,
+                // a time goal ends the game right away in `Playfield::tick`.
+                if self.is_goal_reached() {
+                    return Some(PlayfieldState::finished());
+                }
+
+                spawn_delay.tick();
+                if spawn_delay.is_expired() {
+                    for rows in self.garbage_queue.take_ready() {
+                        if let Some(state) = self.insert_garbage(rows as usize, state) {
+                            return Some(state);
+                        }
+                    }
+
+                    self.spawn_falling_block();
+
+                    if !self.can_move_falling_block_by(0, 0) {
+                        // NOTE: Block out
+                        return Some(PlayfieldState::lost());
+                    } else if self.can_move_falling_block_by(0, -1) {
+                        return Some(PlayfieldState::falling());
+                    } else {
+                        // NOTE: Spawned right on the stack
+                        return Some(PlayfieldState::locking());
+                    }
+                }
+            }
+
+            PlayfieldState::Falling { ref mut gravity_accumulator } => {
+                assert!(self.can_move_falling_block_by(0, -1));
+
+                if self.is_instant_gravity() {
+                    self.drop_falling_block();
+                    return Some(PlayfieldState::locking());
+                }
+
+                if self.is_sonic_drop() {
+                    let distance = self.drop_falling_block();
+                    self.score.award_soft_drop(distance);
+                    return Some(PlayfieldState::locking());
+                }
+
+                // NOTE: Gravity above 1G drops several cells in one frame.
+                *gravity_accumulator = gravity_accumulator.saturating_add(self.gravity());
+                while *gravity_accumulator >= G {
+                    *gravity_accumulator -= G;
+                    self.move_falling_block_by(0, -1);
+                    if self.is_soft_drop_held {
+                        self.score.award_soft_drop(1);
+                    }
+
+                    if !self.can_move_falling_block_by(0, -1) {
+                        return Some(PlayfieldState::locking());
+                    }
+                }
+            }
+
+            PlayfieldState::Locking {
+                ref mut lock_delay,
+                is_immediately,
+            } => {
+                assert!(self.falling_block.is_some());
+                assert!(!self.can_move_falling_block_by(0, -1));
+
+                if is_immediately {
+                    return Some(self.lock());
+                }
+
+                lock_delay.tick();
+                self.max_lock_delay.tick();
+
+                if lock_delay.is_expired() || self.max_lock_delay.is_expired() {
+                    return Some(self.lock());
+                }
+            }
+
+            PlayfieldState::Breaking{
+                ref mut breaking_line_delay,
+                ref mut blink_delay,
+            } => {
+                assert!(self.falling_block.is_none());
+                assert!(self.has_lines_to_break());
+
+                breaking_line_delay.tick();
+                if breaking_line_delay.is_expired() {
+                    self.break_lines();
+                    return Some(PlayfieldState::spawn());
+                }
+
+                blink_delay.tick();
+                if blink_delay.is_expired() {
+                    blink_delay.reset();
+                    self.blink_breaking_lines();
+                }
+            }
+
+            PlayfieldState::Lost | PlayfieldState::Finished => {}
+        }
+
+        None
+    }
+}
+
+impl Playfield {
+    pub fn new(rules: &Rules, seed: u64) -> Playfield {
+        Playfield {
+            state: PlayfieldState::prepare(),
+            raw: PlayfieldRaw::new(rules, seed),
+
+            frame: 0,
+        }
+    }
+
+    pub fn reset(&mut self, seed: u64) {
+        self.state = PlayfieldState::prepare();
+        self.raw.reset(seed);
+        self.frame = 0;
+    }
+
+    pub fn rules(&self) -> &Rules {
+        self.raw.rules()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.raw.seed()
+    }
+
+    pub fn score(&self) -> &Score {
+        self.raw.score()
+    }
+
+    pub fn level(&self) -> &Level {
+        self.raw.level()
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn state(&self) -> &PlayfieldState {
+        &self.state
+    }
+
+    pub fn raw(&self) -> &PlayfieldRaw {
+        &self.raw
+    }
+
+    pub fn time(&self) -> u32 {
+        self.raw.time()
+    }
+
+    pub fn pieces(&self) -> u32 {
+        self.raw.pieces()
+    }
+
+    // NOTE: Garbage sent since the last call.
+    pub fn take_outgoing_garbage(&mut self) -> u32 {
+        let rows = self.raw.outgoing_garbage;
+        self.raw.outgoing_garbage = 0;
+        rows
+    }
+
+    pub fn receive_garbage(&mut self, rows: u32) {
+        if !self.is_ended() {
+            let delay = self.raw.rules.garbage_delay;
+            self.raw.garbage_queue.push(rows, delay);
+        }
+    }
+
+    pub fn garbage_queue(&self) -> &GarbageQueue {
+        &self.raw.garbage_queue
+    }
+
+    // NOTE: Garbage sent in total, after cancelling.
+    pub fn garbage_sent(&self) -> u32 {
+        self.raw.garbage_sent
+    }
+
+    pub fn is_lost(&self) -> bool {
+        matches!(self.state, PlayfieldState::Lost)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, PlayfieldState::Finished)
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.is_lost() || self.is_finished()
+    }
+
+    fn apply_action(&mut self, action: Action) {
+        // NOTE: An action is applied once, even if it makes the state change,
+        // otherwise the new state would handle the same action again.
+        if let Some(state) = self.raw.handle_action(action, &mut self.state) {
+            self.state = state;
+        }
+    }
+
+    // NOTE: The action was pressed.
+    pub fn handle_action(&mut self, action: Action) {
+        match action {
+            Action::MoveLeft => self.raw.auto_shift.press(-1),
+            Action::MoveRight => self.raw.auto_shift.press(1),
+            // NOTE: Held soft drop is applied by gravity every frame, also
+            // after the block leaves the `Locking` state.
+            Action::SoftDrop => self.raw.is_soft_drop_held = true,
+            _ => {}
+        }
+
+        self.apply_action(action);
+    }
+
+    // NOTE: The action was released, only matters for held actions.
+    pub fn release_action(&mut self, action: Action) {
+        match action {
+            Action::MoveLeft => self.raw.auto_shift.release(-1),
+            Action::MoveRight => self.raw.auto_shift.release(1),
+            Action::SoftDrop => self.raw.is_soft_drop_held = false,
+            _ => {}
+        }
+    }
+
+    pub fn handle_input(&mut self, input: Input) {
+        match input {
+            Input::Pressed(action) => self.handle_action(action),
+            Input::Released(action) => self.release_action(action),
+        }
+    }
+
+    fn auto_shift(&mut self) {
+        let cells = self.raw.auto_shift.tick();
+        let direction = self.raw.auto_shift.direction();
+        let action = if direction < 0 {
+            Action::MoveLeft
+        } else {
+            Action::MoveRight
+        };
+
+        for _ in 0..cells.min(self.raw.block.width as u32) {
+            if !self.raw.can_move_falling_block_by(direction, 0) {
+                break;
+            }
+            self.apply_action(action);
+        }
+    }
+
+    // NOTE: Advances the rules by exactly one frame.
+    pub fn tick(&mut self) {
+        while let Some(state) = self.raw.update(&mut self.state) {
+            self.state = state;
+        }
+
+        self.auto_shift();
+
+        let is_started = !matches!(self.state, PlayfieldState::Prepare {..});
+        if is_started && !self.is_ended() && !self.raw.is_goal_reached() {
+            self.raw.time += 1;
+
+            if let Goal::Time(_) = self.raw.rules.goal {
+                if self.raw.is_goal_reached() {
+                    self.state = PlayfieldState::finished();
+                }
+            }
+
+            self.raw.garbage_queue.tick();
+
+            if let Some(state) = self.raw.rise_garbage(&mut self.state) {
+                self.state = state;
+            }
+        }
+
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules_with_gravity(gravity: u32) -> Rules {
+        let mut rules = Rules::new();
+        rules.gravity_table = vec![gravity];
+        rules
+    }
+
+    fn falling_y(playfield: &Playfield) -> i32 {
+        playfield.raw().falling_block().unwrap().y
+    }
+
+    fn is_locking(playfield: &Playfield) -> bool {
+        matches!(*playfield.state(), PlayfieldState::Locking {..})
+    }
+
+    // NOTE: Ticks until the first block is out.
+    fn spawn(playfield: &mut Playfield) {
+        while playfield.raw().falling_block().is_none() {
+            playfield.tick();
+        }
+    }
+
+    #[test]
+    fn instant_gravity_spawns_onto_the_stack() {
+        let mut rules = rules_with_gravity(INSTANT_GRAVITY);
+        rules.garbage_rows = 5;
+        let mut playfield = Playfield::new(&rules, 1);
+
+        spawn(&mut playfield);
+        assert!(is_locking(&playfield));
+        assert_eq!(Some(falling_y(&playfield)), playfield.raw().ghost_block_pos().map(|(_, y)| y));
+        assert!(!playfield.raw().can_move_falling_block_by(0, -1));
+    }
+
+    #[test]
+    fn gravity_above_1g_drops_several_cells_a_frame() {
+        let mut playfield = Playfield::new(&rules_with_gravity(3 * G + G / 2), 1);
+
+        spawn(&mut playfield);
+        let y = falling_y(&playfield);
+        playfield.tick();
+        let first = y - falling_y(&playfield);
+        playfield.tick();
+        let both = y - falling_y(&playfield);
+        assert!(first == 3 || first == 4, "{}", first);
+        assert_eq!(both, 7);
+    }
+
+    #[test]
+    fn sonic_drop_lands_without_locking() {
+        let mut rules = Rules::new();
+        rules.handling.soft_drop_factor = 0;
+        let mut playfield = Playfield::new(&rules, 1);
+
+        spawn(&mut playfield);
+        playfield.handle_action(Action::SoftDrop);
+        playfield.tick();
+        assert!(is_locking(&playfield));
+        assert_eq!(playfield.pieces(), 0);
+        assert_eq!(Some(falling_y(&playfield)), playfield.raw().ghost_block_pos().map(|(_, y)| y));
+    }
+
+    #[test]
+    fn hard_drop_while_locking() {
+        let mut playfield = Playfield::new(&rules_with_gravity(INSTANT_GRAVITY), 1);
+
+        spawn(&mut playfield);
+        assert!(is_locking(&playfield));
+        playfield.handle_action(Action::HardDrop);
+        playfield.tick();
+        assert_eq!(playfield.pieces(), 1);
+    }
+
+    #[test]
+    fn lock_delay_expires() {
+        let mut playfield = Playfield::new(&rules_with_gravity(INSTANT_GRAVITY), 1);
+
+        spawn(&mut playfield);
+        let mut frames = 1;
+        while playfield.pieces() == 0 {
+            playfield.tick();
+            frames += 1;
+        }
+        assert_eq!(frames, 30);
+    }
+
+    #[test]
+    fn lock_delay_resets_after_falling_off_a_ledge() {
+        let mut playfield = Playfield::new(&rules_with_gravity(INSTANT_GRAVITY), 1);
+        // NOTE: A 4 rows high ledge under the spawn, open on the right.
+        for y in 0..4 {
+            for x in 0..6 {
+                playfield.raw.block.set_with_cell(x, y, GARBAGE_CELL);
+            }
+        }
+
+        spawn(&mut playfield);
+        let y = falling_y(&playfield);
+        for _ in 0..20 {
+            playfield.tick();
+        }
+        for _ in 0..4 {
+            playfield.handle_action(Action::MoveRight);
+            playfield.release_action(Action::MoveRight);
+        }
+        assert!(falling_y(&playfield) < y);
+        assert!(is_locking(&playfield));
+
+        for _ in 0..29 {
+            playfield.tick();
+        }
+        assert_eq!(playfield.pieces(), 0);
+        playfield.tick();
+        assert_eq!(playfield.pieces(), 1);
+    }
+
+    // NOTE: The hole of each garbage row from the bottom up.
+    fn garbage_holes(playfield: &Playfield, rows: usize) -> Vec<usize> {
+        let block = playfield.raw().block();
+        (0..rows).map(|row| (0..block.width).find(|&col| block.get(col, row).is_none()).unwrap()).collect()
+    }
+
+    #[test]
+    fn attack_rows_share_a_hole() {
+        let mut playfield = Playfield::new(&Rules::new(), 1);
+        let mut state = PlayfieldState::spawn();
+
+        playfield.raw.insert_garbage(4, &mut state);
+        let holes = garbage_holes(&playfield, 4);
+        assert!(holes.iter().all(|&hole| hole == holes[0]), "{:?}", holes);
+
+        // NOTE: Always a new hole for the next attack at 100%.
+        playfield.raw.insert_garbage(3, &mut state);
+        let holes = garbage_holes(&playfield, 7);
+        assert!(holes[..3].iter().all(|&hole| hole == holes[0]), "{:?}", holes);
+        assert!(holes[3] != holes[0], "{:?}", holes);
+    }
+
+    #[test]
+    fn hold_once_per_block() {
+        let mut playfield = Playfield::new(&Rules::new(), 1);
+
+        spawn(&mut playfield);
+        let first = playfield.raw().falling_block().unwrap().template.shape;
+        let next = playfield.raw().next_templates()[0].shape;
+        playfield.handle_action(Action::Hold);
+        assert_eq!(playfield.raw().held_template().map(|template| template.shape), Some(first));
+        assert_eq!(playfield.raw().falling_block().unwrap().template.shape, next);
+
+        playfield.handle_action(Action::Hold);
+        assert_eq!(playfield.raw().held_template().map(|template| template.shape), Some(first));
+        assert_eq!(playfield.raw().falling_block().unwrap().template.shape, next);
+    }
+}
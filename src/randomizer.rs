@@ -0,0 +1,275 @@
+use std::collections::VecDeque;
+
+use block::{SHAPE_O, SHAPE_S, SHAPE_Z};
+use rng::Rng;
+
+pub trait Randomizer {
+    // NOTE: Returns the shape of the next block, an index into the templates.
+    fn next_shape(&mut self, rng: &mut Rng) -> usize;
+
+    // NOTE: A copy with the same state, the next shapes are the same for
+    // the same rng.
+    fn clone_box(&self) -> Box<dyn Randomizer>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RandomizerKind {
+    Random,
+    Bag7,
+    Bag14,
+    TgmHistory,
+    Nes,
+}
+
+// NOTE: In the order they are listed in the mode setup.
+pub const RANDOMIZERS: [RandomizerKind; 5] = [
+    RandomizerKind::Bag7,
+    RandomizerKind::Bag14,
+    RandomizerKind::TgmHistory,
+    RandomizerKind::Nes,
+    RandomizerKind::Random,
+];
+
+const RANDOMIZER_NAMES: [(RandomizerKind, &str, &str); 5] = [
+    (RandomizerKind::Random, "random", "Random"),
+    (RandomizerKind::Bag7, "bag7", "7-bag"),
+    (RandomizerKind::Bag14, "bag14", "14-bag"),
+    (RandomizerKind::TgmHistory, "tgm_history", "TGM history"),
+    (RandomizerKind::Nes, "nes", "NES"),
+];
+
+impl RandomizerKind {
+    // NOTE: Used in files.
+    pub fn name(&self) -> &'static str {
+        RANDOMIZER_NAMES.iter().find(|&&(kind, ..)| kind == *self).unwrap().1
+    }
+
+    pub fn from_name(name: &str) -> Option<RandomizerKind> {
+        RANDOMIZER_NAMES.iter()
+                        .find(|&&(_, kind_name, _)| kind_name == name)
+                        .map(|&(kind, ..)| kind)
+    }
+
+    pub fn title(&self) -> &'static str {
+        RANDOMIZER_NAMES.iter().find(|&&(kind, ..)| kind == *self).unwrap().2
+    }
+
+    pub fn create(&self, shape_count: usize) -> Box<dyn Randomizer> {
+        match *self {
+            RandomizerKind::Random => Box::new(RandomRandomizer::new(shape_count)),
+            RandomizerKind::Bag7 => Box::new(BagRandomizer::new(shape_count, 1)),
+            RandomizerKind::Bag14 => Box::new(BagRandomizer::new(shape_count, 2)),
+            RandomizerKind::TgmHistory => Box::new(HistoryRandomizer::new(shape_count, 4, 4)),
+            RandomizerKind::Nes => Box::new(NesRandomizer::new(shape_count)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RandomRandomizer {
+    shape_count: usize,
+}
+
+impl RandomRandomizer {
+    pub fn new(shape_count: usize) -> RandomRandomizer {
+        RandomRandomizer {
+            shape_count: shape_count,
+        }
+    }
+}
+
+impl Randomizer for RandomRandomizer {
+    fn next_shape(&mut self, rng: &mut Rng) -> usize {
+        rng.gen_range(self.shape_count)
+    }
+
+    fn clone_box(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+// NOTE: Deals every shape `copies` times in a shuffled order before
+// refilling, 1 copy is the usual 7-bag and 2 copies the 14-bag.
+#[derive(Clone)]
+pub struct BagRandomizer {
+    shape_count: usize,
+    copies: usize,
+    bag: Vec<usize>,
+}
+
+impl BagRandomizer {
+    pub fn new(shape_count: usize, copies: usize) -> BagRandomizer {
+        BagRandomizer {
+            shape_count: shape_count,
+            copies: copies,
+            bag: vec![],
+        }
+    }
+
+    fn refill(&mut self, rng: &mut Rng) {
+        for _ in 0..self.copies {
+            self.bag.extend(0..self.shape_count);
+        }
+
+        // Fisher-Yates
+        for i in (1..self.bag.len()).rev() {
+            let j = rng.gen_range(i + 1);
+            self.bag.swap(i, j);
+        }
+    }
+}
+
+impl Randomizer for BagRandomizer {
+    fn next_shape(&mut self, rng: &mut Rng) -> usize {
+        if self.bag.is_empty() {
+            self.refill(rng);
+        }
+
+        self.bag.pop().unwrap()
+    }
+
+    fn clone_box(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+// NOTE: TGM style randomizer. Remembers the last `history_len` shapes and
+// rolls up to `rolls` times for a shape that isn't in the history. The
+// history starts full of Z and the first block is never S, Z or O.
+#[derive(Clone)]
+pub struct HistoryRandomizer {
+    shape_count: usize,
+    rolls: usize,
+    history: VecDeque<usize>,
+    is_first: bool,
+}
+
+impl HistoryRandomizer {
+    pub fn new(shape_count: usize, history_len: usize, rolls: usize) -> HistoryRandomizer {
+        HistoryRandomizer {
+            shape_count: shape_count,
+            rolls: rolls,
+            history: vec![SHAPE_Z; history_len].into_iter().collect(),
+            is_first: true,
+        }
+    }
+}
+
+impl Randomizer for HistoryRandomizer {
+    fn next_shape(&mut self, rng: &mut Rng) -> usize {
+        let mut shape = rng.gen_range(self.shape_count);
+
+        if self.is_first {
+            self.is_first = false;
+            while shape == SHAPE_S || shape == SHAPE_Z || shape == SHAPE_O {
+                shape = rng.gen_range(self.shape_count);
+            }
+        } else {
+            for _ in 1..self.rolls {
+                if !self.history.contains(&shape) {
+                    break;
+                }
+                shape = rng.gen_range(self.shape_count);
+            }
+        }
+
+        self.history.pop_front();
+        self.history.push_back(shape);
+        shape
+    }
+
+    fn clone_box(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+// NOTE: NES style randomizer. Rolls one extra "dummy" shape, and if it gets
+// the dummy or a repeat of the previous shape, rerolls exactly once.
+#[derive(Clone)]
+pub struct NesRandomizer {
+    shape_count: usize,
+    previous: Option<usize>,
+}
+
+impl NesRandomizer {
+    pub fn new(shape_count: usize) -> NesRandomizer {
+        NesRandomizer {
+            shape_count: shape_count,
+            previous: None,
+        }
+    }
+}
+
+impl Randomizer for NesRandomizer {
+    fn next_shape(&mut self, rng: &mut Rng) -> usize {
+        let mut shape = rng.gen_range(self.shape_count + 1);
+        if shape == self.shape_count || Some(shape) == self.previous {
+            shape = rng.gen_range(self.shape_count);
+        }
+
+        self.previous = Some(shape);
+        shape
+    }
+
+    fn clone_box(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shapes(kind: RandomizerKind, seed: u64, count: usize) -> Vec<usize> {
+        let mut rng = Rng::new(seed);
+        let mut randomizer = kind.create(7);
+        (0..count).map(|_| randomizer.next_shape(&mut rng)).collect()
+    }
+
+    #[test]
+    fn same_seed_same_shapes() {
+        for &kind in &RANDOMIZERS {
+            for seed in 0..8 {
+                let a = shapes(kind, seed, 700);
+                assert_eq!(a, shapes(kind, seed, 700), "{}", kind.name());
+                assert!(a.iter().all(|&shape| shape < 7), "{}", kind.name());
+            }
+        }
+    }
+
+    #[test]
+    fn clone_box_continues_the_same() {
+        for &kind in &RANDOMIZERS {
+            let mut rng = Rng::new(3);
+            let mut randomizer = kind.create(7);
+            for _ in 0..10 {
+                randomizer.next_shape(&mut rng);
+            }
+
+            let mut clone = randomizer.clone_box();
+            let mut clone_rng = rng.clone();
+            for _ in 0..100 {
+                assert_eq!(randomizer.next_shape(&mut rng), clone.next_shape(&mut clone_rng), "{}", kind.name());
+            }
+        }
+    }
+
+    #[test]
+    fn bag7_deals_every_shape_every_7() {
+        for seed in 0..8 {
+            for bag in shapes(RandomizerKind::Bag7, seed, 700).chunks(7) {
+                let mut bag = bag.to_vec();
+                bag.sort();
+                assert_eq!(bag, (0..7).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for &(kind, name, _) in &RANDOMIZER_NAMES {
+            assert_eq!(kind.name(), name);
+            assert_eq!(RandomizerKind::from_name(name), Some(kind));
+        }
+    }
+}
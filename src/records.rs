@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use retris::level::LevelGoal;
+use retris::mode::Mode;
+use retris::playfield::Playfield;
+use retris::randomizer::RandomizerKind;
+use retris::rules::Goal;
+use retris::timer::FPS;
+
+// NOTE: How a mode ranks its games, the value is a time in frames or a
+// score.
+pub enum Ranking {
+    LowestTime,
+    LongestTime,
+    HighestScore,
+}
+
+pub fn ranking(mode: Mode) -> Ranking {
+    match mode {
+        Mode::Sprint | Mode::Cheese => Ranking::LowestTime,
+        Mode::Survival => Ranking::LongestTime,
+        Mode::Ultra | Mode::Marathon | Mode::Versus | Mode::Endless => Ranking::HighestScore,
+    }
+}
+
+// NOTE: Games with different options are ranked apart, `ultra_120` or
+// `marathon_150_1_10` (lines, start level and lines per level). Other
+// randomizers than the 7-bag add their name, `sprint_nes`, so the records
+// from before the randomizer could be picked keep their keys.
+fn key(mode: Mode, playfield: &Playfield) -> String {
+    let key = mode_key(mode, playfield);
+    match playfield.rules().randomizer {
+        RandomizerKind::Bag7 => key,
+        randomizer => format!("{}_{}", key, randomizer.name()),
+    }
+}
+
+fn mode_key(mode: Mode, playfield: &Playfield) -> String {
+    match (mode, playfield.rules().goal) {
+        (Mode::Ultra, Goal::Time(frames)) => format!("{}_{}", mode.name(), frames / FPS),
+        (Mode::Marathon, goal) => {
+            let rules = playfield.rules();
+            let lines = match goal {
+                Goal::Lines(lines) => lines.to_string(),
+                _ => "endless".to_string(),
+            };
+            let level_lines = match rules.level_goal {
+                LevelGoal::Fixed(lines) => lines.to_string(),
+                LevelGoal::Variable => "variable".to_string(),
+            };
+            format!("{}_{}_{}_{}", mode.name(), lines, rules.start_level, level_lines)
+        }
+        (Mode::Survival, _) => {
+            let interval = playfield.rules().rising_garbage.map_or(0, |rising| rising.interval);
+            format!("{}_{}", mode.name(), interval / FPS)
+        }
+        (Mode::Cheese, _) => {
+            let rules = playfield.rules();
+            format!("{}_{}_{}", mode.name(), rules.garbage_rows, rules.garbage_hole_change)
+        }
+        _ => mode.name().to_string(),
+    }
+}
+
+// NOTE: The value the game is ranked by, `None` if the game doesn't count,
+// a timed mode has to be finished.
+fn value(mode: Mode, playfield: &Playfield) -> Option<u64> {
+    match mode {
+        Mode::Sprint | Mode::Cheese if playfield.is_finished() => Some(playfield.time() as u64),
+        Mode::Ultra if playfield.is_finished() => Some(playfield.score().score()),
+        Mode::Sprint | Mode::Cheese | Mode::Ultra => None,
+        Mode::Survival => Some(playfield.time() as u64),
+        Mode::Marathon | Mode::Endless => Some(playfield.score().score()),
+        // NOTE: A versus game is won or lost, there is nothing to beat.
+        Mode::Versus => None,
+    }
+}
+
+// NOTE: The personal best of each mode, saved as `mode = value` lines.
+pub struct Records {
+    path: String,
+    best: HashMap<String, u64>,
+}
+
+impl Records {
+    pub fn load(path: &str) -> Records {
+        let mut records = Records {
+            path: path.to_string(),
+            best: HashMap::new(),
+        };
+
+        let mut text = String::new();
+        if File::open(path).and_then(|mut file| file.read_to_string(&mut text)).is_err() {
+            return records;
+        }
+
+        for line in text.lines() {
+            let parts = line.splitn(2, '=').map(|part| part.trim()).collect::<Vec<_>>();
+            if parts.len() != 2 {
+                continue;
+            }
+            match parts[1].parse::<u64>() {
+                Ok(value) => {
+                    records.best.insert(parts[0].to_string(), value);
+                }
+                Err(_) => println!("{}: ignoring `{}`", path, line),
+            }
+        }
+
+        records
+    }
+
+    fn save(&self) {
+        let mut names = self.best.keys().collect::<Vec<_>>();
+        names.sort();
+
+        let result = File::create(&self.path).and_then(|mut file| {
+            for name in names {
+                writeln!(file, "{} = {}", name, self.best[name])?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            println!("failed to save records, {}: {}", self.path, e);
+        }
+    }
+
+    pub fn best(&self, mode: Mode, playfield: &Playfield) -> Option<u64> {
+        self.best.get(&key(mode, playfield)).cloned()
+    }
+
+    // NOTE: Returns true if the game is a new personal best.
+    pub fn submit(&mut self, mode: Mode, playfield: &Playfield) -> bool {
+        let value = match value(mode, playfield) {
+            Some(value) => value,
+            None => return false,
+        };
+
+        let is_better = match (self.best(mode, playfield), ranking(mode)) {
+            (None, _) => true,
+            (Some(best), Ranking::LowestTime) => value < best,
+            (Some(best), Ranking::LongestTime) |
+            (Some(best), Ranking::HighestScore) => value > best,
+        };
+        if is_better {
+            self.best.insert(key(mode, playfield), value);
+            self.save();
+        }
+        is_better
+    }
+}
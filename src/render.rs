@@ -0,0 +1,280 @@
+use hammer::prelude::*;
+
+use retris::block::*;
+use retris::playfield::*;
+
+use ui::fill_rect;
+
+fn color_to_rgba(color: Color) -> RGBA {
+    rgba(color.r, color.g, color.b, color.a)
+}
+
+pub struct PlayfieldRenderer {
+    block_size_in_pixels: i32,
+
+    is_ghost_visible: bool,
+    is_grid_visible: bool,
+}
+
+impl PlayfieldRenderer {
+    pub fn new(block_size_in_pixels: i32) -> PlayfieldRenderer {
+        PlayfieldRenderer {
+            block_size_in_pixels: block_size_in_pixels,
+
+            is_ghost_visible: true,
+            is_grid_visible: true,
+        }
+    }
+
+    pub fn is_ghost_visible(&self) -> bool {
+        self.is_ghost_visible
+    }
+
+    pub fn set_ghost_visible(&mut self, is_visible: bool) {
+        self.is_ghost_visible = is_visible;
+    }
+
+    pub fn is_grid_visible(&self) -> bool {
+        self.is_grid_visible
+    }
+
+    pub fn set_grid_visible(&mut self, is_visible: bool) {
+        self.is_grid_visible = is_visible;
+    }
+
+    fn width_in_pixels(&self, raw: &PlayfieldRaw) -> i32 {
+        raw.block().width as i32 * self.block_size_in_pixels
+    }
+
+    fn height_in_pixels(&self, raw: &PlayfieldRaw) -> i32 {
+        raw.block().height as i32 * self.block_size_in_pixels
+    }
+
+    fn render_held_blocks(&self, renderer: &mut SoftwareRenderer, raw: &PlayfieldRaw, x: i32, y: i32, blocks_bitmap: &Bitmap) {
+        if let Some(held_template) = raw.held_template() {
+            let block = raw.block_template().block(held_template);
+
+            for (col, row, cell) in block_iter!(block) {
+                let x_offset = col as i32 * self.block_size_in_pixels;
+                let y_offset = (block.height - row) as i32 * self.block_size_in_pixels;
+                let x = x + x_offset;
+                let y = y + (self.height_in_pixels(raw) - y_offset);
+                renderer.blit_sub_bitmap(x + 1, y + 1,
+                                         self.block_size_in_pixels * cell.index,
+                                         0,
+                                         self.block_size_in_pixels,
+                                         self.block_size_in_pixels, blocks_bitmap);
+            }
+        }
+    }
+
+    fn render_falling_block(&self, renderer: &mut SoftwareRenderer, raw: &PlayfieldRaw, x: i32, y: i32, blocks_bitmap: &Bitmap) {
+        let x = self.x_offset_for_cells(x);
+        if let Some(falling_block) = raw.falling_block() {
+            let block = raw.block_template().block(&falling_block.template);
+
+            for (col, row, cell) in block_iter!(block) {
+                // Simply clip the block
+                if falling_block.y + (row as i32) < raw.block().height as i32 {
+                    let x_offset = (falling_block.x + col as i32) * self.block_size_in_pixels;
+                    let y_offset = (falling_block.y + row as i32) * self.block_size_in_pixels;
+                    let x = x + x_offset;
+                    let y = y + y_offset;
+                    renderer.blit_sub_bitmap(x + 1, y + 1,
+                                             self.block_size_in_pixels * cell.index,
+                                             0,
+                                             self.block_size_in_pixels,
+                                             self.block_size_in_pixels, blocks_bitmap);
+                }
+            }
+        }
+    }
+
+    fn render_ghost_block(&self, renderer: &mut SoftwareRenderer, raw: &PlayfieldRaw, x: i32, y: i32) {
+        let x = self.x_offset_for_cells(x);
+        if let (Some(falling_block), Some((ghost_x, ghost_y))) = (raw.falling_block(), raw.ghost_block_pos()) {
+            let block = raw.block_template().block(&falling_block.template);
+
+            for (col, row, cell) in block_iter!(block) {
+                // Simply clip the block
+                if ghost_y + (row as i32) < raw.block().height as i32 {
+                    let x_offset = (ghost_x + col as i32) * self.block_size_in_pixels;
+                    let y_offset = (ghost_y + row as i32) * self.block_size_in_pixels;
+                    let x = x + x_offset;
+                    let y = y + y_offset;
+                    renderer.rect(x + 1,
+                                  y + 1,
+                                  x + self.block_size_in_pixels - 1,
+                                  y + self.block_size_in_pixels - 1,
+                                  color_to_rgba(cell.color));
+                }
+            }
+        }
+    }
+
+    fn render_cells(&self, renderer: &mut SoftwareRenderer, raw: &PlayfieldRaw, x: i32, y: i32, blocks_bitmap: &Bitmap) {
+        let x = self.x_offset_for_cells(x);
+        for (col, row, cell) in block_iter!(raw.block()) {
+            let mut alpha = 1.0;
+            if raw.breaking_lines().contains(&row) {
+                if !raw.is_breaking_lines_visible() {
+                    continue;
+                }
+
+                alpha = 0.5;
+            }
+
+            let x_offset = (col as i32) * self.block_size_in_pixels;
+            let y_offset = (row as i32) * self.block_size_in_pixels;
+            let x = x + x_offset;
+            let y = y + y_offset;
+            if cell.is_garbage() {
+                let color = cell.color;
+                fill_rect(renderer, x + 1, y + 1,
+                          x + self.block_size_in_pixels,
+                          y + self.block_size_in_pixels,
+                          rgba(color.r * alpha, color.g * alpha, color.b * alpha, 1.0));
+                continue;
+            }
+
+            renderer.blit_sub_bitmap_alpha(x + 1, y + 1,
+                                           self.block_size_in_pixels * cell.index,
+                                           0,
+                                           self.block_size_in_pixels,
+                                           self.block_size_in_pixels, blocks_bitmap,
+                                           alpha);
+        }
+    }
+
+    fn render_grids(&self, renderer: &mut SoftwareRenderer, raw: &PlayfieldRaw, x: i32, y: i32, color: RGBA) {
+        let x = self.x_offset_for_cells(x);
+        for row in 1..raw.block().height {
+            let y_offset = row as i32 * self.block_size_in_pixels;
+            renderer.hline(y + y_offset, x, x + self.width_in_pixels(raw), color);
+        }
+
+        for col in 1..raw.block().width {
+            let x_offset = col as i32 * self.block_size_in_pixels;
+            renderer.vline(x + x_offset, y, y + self.height_in_pixels(raw), color);
+        }
+    }
+
+    fn render_borders(&self, renderer: &mut SoftwareRenderer, raw: &PlayfieldRaw, x: i32, y: i32, color: RGBA) {
+        let x = self.x_offset_for_cells(x);
+        renderer.rect(x, y,
+                      x + self.width_in_pixels(raw),
+                      y + self.height_in_pixels(raw),
+                      color);
+    }
+
+    // NOTE: The incoming garbage as a bar left of the cells, one cell high
+    // per row. Garbage that enters at the next spawn is red, garbage still
+    // waiting is orange.
+    fn render_garbage_meter(&self, renderer: &mut SoftwareRenderer, playfield: &Playfield, x: i32, y: i32) {
+        let raw = playfield.raw();
+        let x1 = self.x_offset_for_cells(x) - 2;
+        let x0 = x1 - self.block_size_in_pixels / 3;
+        let max_y = y + self.height_in_pixels(raw);
+
+        let mut y0 = y;
+        for garbage in playfield.garbage_queue().incoming() {
+            let color = if garbage.is_ready() {
+                rgba(0.9, 0.1, 0.1, 1.0)
+            } else {
+                rgba(1.0, 0.6, 0.1, 1.0)
+            };
+            let y1 = (y0 + garbage.rows as i32 * self.block_size_in_pixels).min(max_y);
+            fill_rect(renderer, x0, y0 + 1, x1, y1, color);
+            y0 = y1;
+        }
+    }
+
+    fn render_next_blocks(&self, renderer: &mut SoftwareRenderer, raw: &PlayfieldRaw, x: i32, y: i32, blocks_bitmap: &Bitmap) {
+        let x = self.x_offset_for_next_blocks(raw, x);
+        for (i, template) in raw.next_templates().iter().enumerate() {
+            let block = raw.block_template().block(template);
+
+            for (col, row, cell) in block_iter!(block) {
+                let x_offset = col as i32 * self.block_size_in_pixels;
+                let y_offset = (block.height - row) as i32 * self.block_size_in_pixels;
+                let x = x + x_offset;
+                let y = y + (self.height_in_pixels(raw) - y_offset) -
+                        i as i32 * 4 * self.block_size_in_pixels;
+                renderer.blit_sub_bitmap(x + 1, y + 1,
+                                         self.block_size_in_pixels * cell.index,
+                                         0,
+                                         self.block_size_in_pixels,
+                                         self.block_size_in_pixels, blocks_bitmap);
+            }
+        }
+    }
+
+    // NOTE: The area covered by the cells, (x0, y0, x1, y1), for the
+    // screens drawn on top of the playfield.
+    pub fn cells_rect(&self, playfield: &Playfield, x: i32, y: i32) -> (i32, i32, i32, i32) {
+        let raw = playfield.raw();
+        let x = self.x_offset_for_cells(x);
+        (x, y, x + self.width_in_pixels(raw), y + self.height_in_pixels(raw))
+    }
+
+    // NOTE: The whole playfield, held piece, cells and next pieces.
+    pub fn size_in_pixels(&self, playfield: &Playfield) -> (i32, i32) {
+        let raw = playfield.raw();
+        (self.x_offset_for_next_blocks(raw, 0) + 4 * self.block_size_in_pixels, self.height_in_pixels(raw))
+    }
+
+    // NOTE: The area left of the cells under the held piece.
+    pub fn hud_rect(&self, playfield: &Playfield, x: i32, y: i32) -> (i32, i32, i32, i32) {
+        let raw = playfield.raw();
+        (x, y, x + 4 * self.block_size_in_pixels, y + self.height_in_pixels(raw) - 5 * self.block_size_in_pixels)
+    }
+
+    // NOTE: The game over animation, the bottom `rows` rows are filled
+    // with grey, the stack a bit lighter than the empty cells.
+    pub fn render_fill(&self, renderer: &mut SoftwareRenderer, playfield: &Playfield, x: i32, y: i32, rows: usize) {
+        let raw = playfield.raw();
+        let block = raw.block();
+        let x = self.x_offset_for_cells(x);
+        for row in 0..rows.min(block.height) {
+            for col in 0..block.width {
+                let color = if block.get(col, row).is_some() {
+                    rgba(0.6, 0.6, 0.6, 1.0)
+                } else {
+                    rgba(0.3, 0.3, 0.3, 1.0)
+                };
+                let x = x + col as i32 * self.block_size_in_pixels;
+                let y = y + row as i32 * self.block_size_in_pixels;
+                fill_rect(renderer, x + 1, y + 1,
+                          x + self.block_size_in_pixels,
+                          y + self.block_size_in_pixels,
+                          color);
+            }
+        }
+    }
+
+    fn x_offset_for_cells(&self, x: i32) -> i32 {
+        x + 5 * self.block_size_in_pixels
+    }
+
+    fn x_offset_for_next_blocks(&self, raw: &PlayfieldRaw, x: i32) -> i32 {
+        self.x_offset_for_cells(x) + self.width_in_pixels(raw) + self.block_size_in_pixels
+    }
+
+    pub fn render(&self, renderer: &mut SoftwareRenderer, playfield: &Playfield, x: i32, y: i32, blocks_bitmap: &Bitmap) {
+        let raw = playfield.raw();
+        self.render_held_blocks(renderer, raw, x, y, blocks_bitmap);
+        if raw.falling_block().is_some() {
+            if self.is_ghost_visible {
+                self.render_ghost_block(renderer, raw, x, y);
+            }
+            self.render_falling_block(renderer, raw, x, y, blocks_bitmap);
+        }
+        self.render_cells(renderer, raw, x, y, blocks_bitmap);
+        if self.is_grid_visible {
+            self.render_grids(renderer, raw, x, y, rgba(0.2, 0.2, 0.2, 1.0));
+        }
+        self.render_borders(renderer, raw, x, y, rgba(1.0, 1.0, 1.0, 1.0));
+        self.render_garbage_meter(renderer, playfield, x, y);
+        self.render_next_blocks(renderer, raw, x, y, blocks_bitmap);
+    }
+}
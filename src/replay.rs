@@ -0,0 +1,550 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use level::LevelGoal;
+use mode::Mode;
+use playfield::{Action, Input, Playfield};
+use randomizer::RandomizerKind;
+use rules::{Goal, RisingGarbage, Rules};
+
+// NOTE: A replay is the mode, the rules, the seed and every input with the
+// frame it happened on and the player, the rules are deterministic so that
+// is enough to play the game again. Both boards of a local versus game are
+// in one replay, online games are not recorded since the remote inputs
+// arrive late or are predicted. The file is plain text:
+//
+//   retris-replay 2
+//   mode sprint
+//   seed 42
+//   width 10
+//   ...
+//   frames 3600
+//   events
+//   0 0 p move_left
+//   12 0 r move_left
+//
+// Bump `REPLAY_VERSION` whenever the format or the rules change in a way
+// that makes old replays play differently.
+pub const REPLAY_VERSION: u32 = 2;
+
+// NOTE: Version 1 events have no player, they play the same as player 1 of
+// version 2.
+const OLDEST_REPLAY_VERSION: u32 = 1;
+
+const MAGIC: &str = "retris-replay";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReplayEvent {
+    pub frame: u32,
+    // NOTE: 0 is player 1.
+    pub player: usize,
+    pub input: Input,
+}
+
+#[derive(Clone, Debug)]
+pub struct Replay {
+    // NOTE: For the HUD and results, the rules alone decide the game.
+    pub mode: Mode,
+    pub rules: Rules,
+    pub seed: u64,
+    // NOTE: How many frames the game lasted.
+    pub frames: u32,
+    pub events: Vec<ReplayEvent>,
+}
+
+fn parse_value<T: ::std::str::FromStr>(key: &str, values: &[&str]) -> Result<T, String> {
+    values.first()
+          .and_then(|value| value.parse::<T>().ok())
+          .ok_or_else(|| format!("bad value for `{}`", key))
+}
+
+// NOTE: For the rules that can't be 0, the playfield would assert or never
+// level up.
+fn parse_nonzero<T: ::std::str::FromStr + PartialEq + Default>(key: &str, values: &[&str]) -> Result<T, String> {
+    let value = parse_value(key, values)?;
+    if value == T::default() {
+        return Err(format!("`{}` can't be 0", key));
+    }
+    Ok(value)
+}
+
+impl Replay {
+    pub fn new(mode: Mode, rules: &Rules, seed: u64) -> Replay {
+        Replay {
+            mode: mode,
+            rules: rules.clone(),
+            seed: seed,
+            frames: 0,
+            events: vec![],
+        }
+    }
+
+    pub fn record(&mut self, frame: u32, player: usize, input: Input) {
+        assert!(player < self.mode.player_count());
+        if let Some(last) = self.events.last() {
+            assert!(last.frame <= frame);
+        }
+        self.events.push(ReplayEvent {
+            frame: frame,
+            player: player,
+            input: input,
+        });
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> ::std::io::Result<()> {
+        let rules = &self.rules;
+
+        writeln!(w, "{} {}", MAGIC, REPLAY_VERSION)?;
+        writeln!(w, "mode {}", self.mode.name())?;
+        writeln!(w, "seed {}", self.seed)?;
+        writeln!(w, "width {}", rules.width)?;
+        writeln!(w, "height {}", rules.height)?;
+        writeln!(w, "randomizer {}", rules.randomizer.name())?;
+        writeln!(w, "start_level {}", rules.start_level)?;
+        match rules.level_goal {
+            LevelGoal::Fixed(lines) => writeln!(w, "level_goal fixed {}", lines)?,
+            LevelGoal::Variable => writeln!(w, "level_goal variable")?,
+        }
+        let gravity_table = rules.gravity_table.iter().map(|gravity| gravity.to_string()).collect::<Vec<_>>();
+        writeln!(w, "gravity_table {}", gravity_table.join(" "))?;
+        writeln!(w, "das {}", rules.handling.das)?;
+        writeln!(w, "arr {}", rules.handling.arr)?;
+        writeln!(w, "das_cut {}", rules.handling.das_cut)?;
+        writeln!(w, "soft_drop_factor {}", rules.handling.soft_drop_factor)?;
+        match rules.goal {
+            Goal::None => writeln!(w, "goal none")?,
+            Goal::Lines(lines) => writeln!(w, "goal lines {}", lines)?,
+            Goal::Time(frames) => writeln!(w, "goal time {}", frames)?,
+            Goal::Garbage => writeln!(w, "goal garbage")?,
+        }
+        writeln!(w, "garbage_rows {}", rules.garbage_rows)?;
+        writeln!(w, "garbage_hole_change {}", rules.garbage_hole_change)?;
+        match rules.rising_garbage {
+            Some(rising) => {
+                writeln!(w, "rising_garbage {} {} {}", rising.interval, rising.min_interval, rising.acceleration)?
+            }
+            None => writeln!(w, "rising_garbage none")?,
+        }
+        writeln!(w, "garbage_delay {}", rules.garbage_delay)?;
+        writeln!(w, "frames {}", self.frames)?;
+
+        writeln!(w, "events")?;
+        for event in &self.events {
+            let (kind, action) = match event.input {
+                Input::Pressed(action) => ("p", action),
+                Input::Released(action) => ("r", action),
+            };
+            writeln!(w, "{} {} {} {}", event.frame, event.player, kind, action.name())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+
+        File::create(path).and_then(|mut file| self.write(&mut file))
+                          .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Replay, String> {
+        let mut lines = text.lines().enumerate();
+
+        match lines.next() {
+            Some((_, header)) => {
+                let header = header.split_whitespace().collect::<Vec<_>>();
+                if header.len() != 2 || header[0] != MAGIC {
+                    return Err("not a replay".to_string());
+                }
+                match header[1].parse::<u32>() {
+                    Ok(version) if (OLDEST_REPLAY_VERSION..=REPLAY_VERSION).contains(&version) => {}
+                    _ => {
+                        return Err(format!("unsupported replay version {}, expected {}",
+                                           header[1], REPLAY_VERSION));
+                    }
+                }
+            }
+            None => return Err("empty replay".to_string()),
+        }
+
+        // NOTE: Keys missing from the file keep the default rules.
+        let mut replay = Replay::new(Mode::Endless, &Rules::new(), 0);
+        let mut is_events = false;
+
+        for (i, line) in lines {
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            if parts.is_empty() {
+                continue;
+            }
+
+            let result = if is_events {
+                replay.parse_event(&parts)
+            } else if parts[0] == "events" {
+                is_events = true;
+                Ok(())
+            } else {
+                replay.parse_header(parts[0], &parts[1..])
+            };
+
+            result.map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+
+        Ok(replay)
+    }
+
+    fn parse_header(&mut self, key: &str, values: &[&str]) -> Result<(), String> {
+        let rules = &mut self.rules;
+        match key {
+            "mode" => {
+                self.mode = values.first()
+                                  .and_then(|name| Mode::from_name(name))
+                                  .ok_or_else(|| format!("bad value for `{}`", key))?;
+            }
+            "seed" => self.seed = parse_value(key, values)?,
+            "frames" => self.frames = parse_value(key, values)?,
+            "width" => rules.width = parse_nonzero(key, values)?,
+            "height" => rules.height = parse_nonzero(key, values)?,
+            "randomizer" => {
+                rules.randomizer = values.first()
+                                         .and_then(|name| RandomizerKind::from_name(name))
+                                         .ok_or_else(|| format!("bad value for `{}`", key))?;
+            }
+            "start_level" => rules.start_level = parse_nonzero(key, values)?,
+            "level_goal" => {
+                rules.level_goal = match values.first() {
+                    Some(&"fixed") => LevelGoal::Fixed(parse_nonzero(key, &values[1..])?),
+                    Some(&"variable") => LevelGoal::Variable,
+                    _ => return Err(format!("bad value for `{}`", key)),
+                };
+            }
+            "gravity_table" => {
+                let mut gravity_table = vec![];
+                for value in values {
+                    gravity_table.push(parse_value(key, &[*value])?);
+                }
+                if gravity_table.is_empty() {
+                    return Err(format!("bad value for `{}`", key));
+                }
+                rules.gravity_table = gravity_table;
+            }
+            "das" => rules.handling.das = parse_value(key, values)?,
+            "arr" => rules.handling.arr = parse_value(key, values)?,
+            "das_cut" => rules.handling.das_cut = parse_value(key, values)?,
+            "soft_drop_factor" => rules.handling.soft_drop_factor = parse_value(key, values)?,
+            "goal" => {
+                rules.goal = match values.first() {
+                    Some(&"none") => Goal::None,
+                    Some(&"lines") => Goal::Lines(parse_value(key, &values[1..])?),
+                    Some(&"time") => Goal::Time(parse_value(key, &values[1..])?),
+                    Some(&"garbage") => Goal::Garbage,
+                    _ => return Err(format!("bad value for `{}`", key)),
+                };
+            }
+            "garbage_rows" => rules.garbage_rows = parse_value(key, values)?,
+            "garbage_hole_change" => rules.garbage_hole_change = parse_value(key, values)?,
+            "rising_garbage" => {
+                rules.rising_garbage = match values.first() {
+                    Some(&"none") => None,
+                    _ if values.len() == 3 => {
+                        Some(RisingGarbage {
+                            interval: parse_value(key, &values[0..])?,
+                            min_interval: parse_value(key, &values[1..])?,
+                            acceleration: parse_value(key, &values[2..])?,
+                        })
+                    }
+                    _ => return Err(format!("bad value for `{}`", key)),
+                };
+            }
+            "garbage_delay" => rules.garbage_delay = parse_value(key, values)?,
+            _ => return Err(format!("unknown key `{}`", key)),
+        }
+        Ok(())
+    }
+
+    fn parse_event(&mut self, parts: &[&str]) -> Result<(), String> {
+        let (frame, player, kind, action) = match *parts {
+            [frame, kind, action] => (frame, "0", kind, action),
+            [frame, player, kind, action] => (frame, player, kind, action),
+            _ => return Err("expected `frame player p|r action`".to_string()),
+        };
+
+        let frame = parse_value("frame", &[frame])?;
+        let player = parse_value::<usize>("player", &[player])?;
+        if player >= self.mode.player_count() {
+            return Err(format!("no player {} in {}", player + 1, self.mode.name()));
+        }
+        let action = Action::from_name(action).ok_or_else(|| format!("unknown action `{}`", action))?;
+        let input = match kind {
+            "p" => Input::Pressed(action),
+            "r" => Input::Released(action),
+            _ => return Err(format!("unknown input `{}`", kind)),
+        };
+
+        if let Some(last) = self.events.last() {
+            if last.frame > frame {
+                return Err("events out of order".to_string());
+            }
+        }
+
+        self.record(frame, player, input);
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay, String> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+                        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Replay::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+// NOTE: Feeds the recorded inputs back into playfields created from the
+// replay's rules and seed, one for each player.
+pub struct ReplayPlayer {
+    replay: Replay,
+    next_event: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> ReplayPlayer {
+        ReplayPlayer {
+            replay: replay,
+            next_event: 0,
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn create_playfield(&self) -> Playfield {
+        Playfield::new(&self.replay.rules, self.replay.seed)
+    }
+
+    // NOTE: Call before every `Playfield::tick`, applies the inputs that
+    // happened before this frame. The playfields are in player order and
+    // tick together.
+    pub fn apply(&mut self, playfields: &mut [&mut Playfield]) {
+        while let Some(event) = self.replay.events.get(self.next_event) {
+            if event.frame > playfields[0].frame() {
+                break;
+            }
+            if let Some(playfield) = playfields.get_mut(event.player) {
+                playfield.handle_input(event.input);
+            }
+            self.next_event += 1;
+        }
+    }
+
+    pub fn is_finished(&self, playfield: &Playfield) -> bool {
+        playfield.frame() >= self.replay.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng::Rng;
+
+    const ACTIONS: [Action; 7] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::RotateClockwise,
+        Action::HardDrop,
+        Action::Hold,
+        Action::Rotate180,
+    ];
+
+    fn random_input(rng: &mut Rng) -> Option<Input> {
+        if rng.gen_range(8) != 0 {
+            return None;
+        }
+        let action = ACTIONS[rng.gen_range(ACTIONS.len())];
+        if rng.gen_range(2) == 0 {
+            Some(Input::Pressed(action))
+        } else {
+            Some(Input::Released(action))
+        }
+    }
+
+    fn board(playfield: &Playfield) -> Vec<Option<i32>> {
+        playfield.raw().block().data.iter().map(|cell| cell.map(|cell| cell.index)).collect()
+    }
+
+    fn assert_same_game(played: &Playfield, replayed: &Playfield) {
+        assert_eq!(board(played), board(replayed));
+        assert_eq!(played.score().score(), replayed.score().score());
+        assert_eq!(played.score().lines(), replayed.score().lines());
+        assert_eq!(played.pieces(), replayed.pieces());
+        assert_eq!(played.frame(), replayed.frame());
+        assert_eq!(played.is_lost(), replayed.is_lost());
+    }
+
+    fn round_trip(replay: &Replay) -> Replay {
+        let mut text = vec![];
+        replay.write(&mut text).unwrap();
+        Replay::parse(::std::str::from_utf8(&text).unwrap()).unwrap()
+    }
+
+    fn text(replay: &Replay) -> String {
+        let mut text = vec![];
+        replay.write(&mut text).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn save_and_parse_every_key() {
+        let mut rules = Rules::new();
+        rules.width = 12;
+        rules.height = 24;
+        rules.randomizer = RandomizerKind::Nes;
+        rules.start_level = 4;
+        rules.level_goal = LevelGoal::Variable;
+        rules.gravity_table = vec![100, 200, 300];
+        rules.handling.das = 7;
+        rules.handling.arr = 0;
+        rules.handling.das_cut = 3;
+        rules.handling.soft_drop_factor = 0;
+        rules.goal = Goal::Lines(25);
+        rules.garbage_rows = 6;
+        rules.garbage_hole_change = 40;
+        rules.rising_garbage = Some(RisingGarbage {
+            interval: 300,
+            min_interval: 60,
+            acceleration: 3,
+        });
+        rules.garbage_delay = 12;
+
+        let mut replay = Replay::new(Mode::Versus, &rules, 0xdead_beef);
+        replay.record(0, 0, Input::Pressed(Action::MoveLeft));
+        replay.record(0, 1, Input::Pressed(Action::Hold));
+        replay.record(12, 0, Input::Released(Action::MoveLeft));
+        replay.record(40, 1, Input::Pressed(Action::Rotate180));
+        replay.frames = 41;
+
+        let parsed = round_trip(&replay);
+        assert_eq!(parsed.mode, Mode::Versus);
+        assert_eq!(parsed.seed, 0xdead_beef);
+        assert_eq!(parsed.frames, 41);
+        assert_eq!(parsed.events, replay.events);
+        assert_eq!(parsed.rules.randomizer, RandomizerKind::Nes);
+        assert_eq!(parsed.rules.gravity_table, vec![100, 200, 300]);
+        assert_eq!(parsed.rules.rising_garbage, rules.rising_garbage);
+        // NOTE: Every key is written, so the same text means the same rules.
+        assert_eq!(text(&parsed), text(&replay));
+
+        for &(goal, level_goal) in &[(Goal::None, LevelGoal::Fixed(7)),
+                                     (Goal::Time(3600), LevelGoal::Fixed(10)),
+                                     (Goal::Garbage, LevelGoal::Variable)] {
+            rules.goal = goal;
+            rules.level_goal = level_goal;
+            rules.rising_garbage = None;
+            let replay = Replay::new(Mode::Sprint, &rules, 1);
+            let parsed = round_trip(&replay);
+            assert_eq!(parsed.rules.goal, goal);
+            assert_eq!(parsed.rules.level_goal, level_goal);
+            assert_eq!(parsed.rules.rising_garbage, None);
+            assert_eq!(text(&parsed), text(&replay));
+        }
+    }
+
+    #[test]
+    fn playback_plays_the_same_game() {
+        let mut rules = Rules::new();
+        rules.garbage_rows = 4;
+        let mut playfield = Playfield::new(&rules, 7);
+        let mut replay = Replay::new(Mode::Cheese, &rules, 7);
+        let mut rng = Rng::new(1);
+        while playfield.frame() < 5000 && !playfield.is_ended() {
+            if let Some(input) = random_input(&mut rng) {
+                replay.record(playfield.frame(), 0, input);
+                playfield.handle_input(input);
+            }
+            playfield.tick();
+        }
+        replay.frames = playfield.frame();
+        assert!(playfield.pieces() > 10);
+
+        let mut player = ReplayPlayer::new(round_trip(&replay));
+        let mut replayed = player.create_playfield();
+        while !player.is_finished(&replayed) {
+            player.apply(&mut [&mut replayed]);
+            replayed.tick();
+        }
+        assert_same_game(&playfield, &replayed);
+    }
+
+    #[test]
+    fn playback_plays_both_versus_boards() {
+        let rules = Mode::Versus.rules(&::mode::ModeOptions::new());
+        let mut playfields = [Playfield::new(&rules, 3), Playfield::new(&rules, 3)];
+        let mut replay = Replay::new(Mode::Versus, &rules, 3);
+        let mut rng = Rng::new(2);
+        while playfields[0].frame() < 5000 && !playfields.iter().any(|playfield| playfield.is_ended()) {
+            for (player, playfield) in playfields.iter_mut().enumerate() {
+                if let Some(input) = random_input(&mut rng) {
+                    replay.record(playfield.frame(), player, input);
+                    playfield.handle_input(input);
+                }
+            }
+            playfields[0].tick();
+            playfields[1].tick();
+            let garbage = playfields[0].take_outgoing_garbage();
+            playfields[1].receive_garbage(garbage);
+            let garbage = playfields[1].take_outgoing_garbage();
+            playfields[0].receive_garbage(garbage);
+        }
+        replay.frames = playfields[0].frame();
+
+        let mut player = ReplayPlayer::new(round_trip(&replay));
+        let mut replayed = [player.create_playfield(), player.create_playfield()];
+        while !player.is_finished(&replayed[0]) {
+            {
+                let (first, second) = replayed.split_at_mut(1);
+                player.apply(&mut [&mut first[0], &mut second[0]]);
+            }
+            replayed[0].tick();
+            replayed[1].tick();
+            let garbage = replayed[0].take_outgoing_garbage();
+            replayed[1].receive_garbage(garbage);
+            let garbage = replayed[1].take_outgoing_garbage();
+            replayed[0].receive_garbage(garbage);
+        }
+        assert_same_game(&playfields[0], &replayed[0]);
+        assert_same_game(&playfields[1], &replayed[1]);
+    }
+
+    #[test]
+    fn version_1_events_are_player_1() {
+        let replay = Replay::parse("retris-replay 1\nseed 5\nframes 20\nevents\n3 p hard_drop\n").unwrap();
+        assert_eq!(replay.mode, Mode::Endless);
+        assert_eq!(replay.events, vec![ReplayEvent {
+            frame: 3,
+            player: 0,
+            input: Input::Pressed(Action::HardDrop),
+        }]);
+    }
+
+    #[test]
+    fn bad_replays_are_rejected() {
+        let error = |text: &str| Replay::parse(text).unwrap_err();
+        assert_eq!(error(""), "empty replay");
+        assert_eq!(error("retris-save 2\n"), "not a replay");
+        assert_eq!(error("retris-replay 99\n"), "unsupported replay version 99, expected 2");
+        assert_eq!(error("retris-replay 0\n"), "unsupported replay version 0, expected 2");
+        assert_eq!(error("retris-replay 2\nwidth 0\n"), "line 2: `width` can't be 0");
+        assert_eq!(error("retris-replay 2\nlevel_goal fixed 0\n"), "line 2: `level_goal` can't be 0");
+        assert_eq!(error("retris-replay 2\nlives 3\n"), "line 2: unknown key `lives`");
+        assert_eq!(error("retris-replay 2\nmode tetris\n"), "line 2: bad value for `mode`");
+        assert_eq!(error("retris-replay 2\nevents\n0 1 p hold\n"), "line 3: no player 2 in endless");
+        assert_eq!(error("retris-replay 2\nevents\n5 0 p hold\n4 0 r hold\n"), "line 4: events out of order");
+        assert_eq!(error("retris-replay 2\nevents\n5 0 x hold\n"), "line 3: unknown input `x`");
+        assert_eq!(error("retris-replay 2\nevents\n5 0\n"), "line 3: expected `frame player p|r action`");
+    }
+}
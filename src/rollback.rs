@@ -0,0 +1,684 @@
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::mem;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use net::{Connection, Message};
+use playfield::{Action, Input, Playfield};
+use rng::Rng;
+use rules::Rules;
+
+// NOTE: Rollback versus. Both sides simulate both playfields, a
+// frame only needs the inputs of the two players. The remote inputs arrive
+// late, so the session predicts them (no new presses or releases, held
+// keys stay held), keeps a snapshot of every frame that used a prediction,
+// and when the real inputs differ it restores the snapshot of that frame
+// and plays the frames again. The playfields are deterministic, the same
+// inputs on the same frames always give the same boards.
+//
+// Inputs are sent over UDP, every packet repeats all the inputs the other
+// side hasn't acknowledged yet, so a lost packet is covered by the next:
+//
+//   retris-rollback 1 <ack> <first frame> <inputs of each frame>...
+//
+// `ack` is the number of frames of the receiver's inputs the sender has,
+// the inputs of a frame are `-` or a comma separated list like
+// `p:move_left,r:hard_drop`.
+//
+// A game starts like a TCP one, see `net`, then both sides swap the ports of
+// their UDP sockets with `link` and the TCP connection is done.
+pub const ROLLBACK_VERSION: u32 = 1;
+
+// NOTE: How far the local player may get ahead of the last confirmed
+// frame, the session stalls beyond it.
+pub const MAX_ROLLBACK_FRAMES: u32 = 30;
+
+// NOTE: The remote is gone after this long without a packet.
+pub const REMOTE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MAGIC: &str = "retris-rollback";
+
+// NOTE: Both players of a versus game, player 1 first. Garbage sent on a
+// frame goes to the queue of the other board on the same frame. Once a
+// board ends both stop, every frame after it has the same boards.
+#[derive(Clone)]
+pub struct Versus {
+    playfields: [Playfield; 2],
+    frame: u32,
+}
+
+impl Versus {
+    pub fn new(rules: &Rules, seed: u64) -> Versus {
+        Versus {
+            playfields: [Playfield::new(rules, seed), Playfield::new(rules, seed)],
+            frame: 0,
+        }
+    }
+
+    pub fn playfield(&self, player: usize) -> &Playfield {
+        &self.playfields[player]
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.playfields.iter().any(|playfield| playfield.is_ended())
+    }
+
+    pub fn tick(&mut self, inputs: [&[Input]; 2]) {
+        self.frame += 1;
+        if self.is_ended() {
+            return;
+        }
+
+        for (playfield, inputs) in self.playfields.iter_mut().zip(inputs.iter()) {
+            for &input in inputs.iter() {
+                playfield.handle_input(input);
+            }
+            playfield.tick();
+        }
+
+        let rows = self.playfields[0].take_outgoing_garbage();
+        self.playfields[1].receive_garbage(rows);
+        let rows = self.playfields[1].take_outgoing_garbage();
+        self.playfields[0].receive_garbage(rows);
+    }
+
+    // NOTE: To compare two simulations, the same frame must give the same
+    // checksum on both sides.
+    pub fn checksum(&self) -> u64 {
+        // NOTE: FNV-1a over the values.
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let mut mix = |value: u64| {
+            hash = (hash ^ value).wrapping_mul(0x100_0000_01b3);
+        };
+
+        for playfield in &self.playfields {
+            let raw = playfield.raw();
+            mix(playfield.frame() as u64);
+            mix(playfield.score().score());
+            mix(playfield.garbage_queue().rows() as u64);
+            for cell in &raw.block().data {
+                mix(cell.map_or(0, |cell| cell.index as u64 + 1));
+            }
+            if let Some(falling_block) = raw.falling_block() {
+                mix(falling_block.x as u64);
+                mix(falling_block.y as u64);
+                mix((falling_block.template.shape * 4 + falling_block.template.order) as u64);
+            }
+        }
+        hash
+    }
+}
+
+fn format_inputs(inputs: &[Input]) -> String {
+    if inputs.is_empty() {
+        return "-".to_string();
+    }
+
+    inputs.iter()
+          .map(|&input| {
+              match input {
+                  Input::Pressed(action) => format!("p:{}", action.name()),
+                  Input::Released(action) => format!("r:{}", action.name()),
+              }
+          })
+          .collect::<Vec<_>>()
+          .join(",")
+}
+
+fn parse_inputs(text: &str) -> Result<Vec<Input>, String> {
+    if text == "-" {
+        return Ok(vec![]);
+    }
+
+    let mut inputs = vec![];
+    for part in text.split(',') {
+        let (kind, name) = part.split_at(part.find(':').ok_or_else(|| format!("bad input `{}`", part))?);
+        let action = Action::from_name(&name[1..]).ok_or_else(|| format!("unknown action `{}`", &name[1..]))?;
+        inputs.push(match kind {
+            "p" => Input::Pressed(action),
+            "r" => Input::Released(action),
+            _ => return Err(format!("bad input `{}`", part)),
+        });
+    }
+    Ok(inputs)
+}
+
+fn parse_value<T: ::std::str::FromStr>(key: &str, value: Option<&&str>) -> Result<T, String> {
+    value.and_then(|value| value.parse::<T>().ok())
+         .ok_or_else(|| format!("bad value for `{}`", key))
+}
+
+// NOTE: The inputs of some frames of one player.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputPacket {
+    pub ack: u32,
+    pub first_frame: u32,
+    pub frames: Vec<Vec<Input>>,
+}
+
+impl InputPacket {
+    pub fn format(&self) -> String {
+        let mut text = format!("{} {} {} {}", MAGIC, ROLLBACK_VERSION, self.ack, self.first_frame);
+        for inputs in &self.frames {
+            text.push(' ');
+            text.push_str(&format_inputs(inputs));
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<InputPacket, String> {
+        let parts = text.split_whitespace().collect::<Vec<_>>();
+        if parts.first() != Some(&MAGIC) {
+            return Err("not a rollback packet".to_string());
+        }
+        let version = parse_value::<u32>("version", parts.get(1))?;
+        if version != ROLLBACK_VERSION {
+            return Err(format!("unsupported rollback version {}, expected {}", version, ROLLBACK_VERSION));
+        }
+
+        let mut frames = vec![];
+        for part in parts.iter().skip(4) {
+            frames.push(parse_inputs(part)?);
+        }
+        Ok(InputPacket {
+            ack: parse_value("ack", parts.get(2))?,
+            first_frame: parse_value("frame", parts.get(3))?,
+            frames: frames,
+        })
+    }
+}
+
+// NOTE: A UDP socket talking to one peer. The delay and the loss are
+// simulated on the sending side, for testing bad links on loopback.
+pub struct UdpLink {
+    socket: UdpSocket,
+    // NOTE: Nothing is sent or received before it is known.
+    peer: Option<SocketAddr>,
+
+    delay: Duration,
+    // NOTE: Chance in percent a packet is dropped.
+    loss: u32,
+    rng: Rng,
+    delayed: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl UdpLink {
+    pub fn new(local: &str, peer: &str) -> Result<UdpLink, String> {
+        let mut link = UdpLink::bind(local)?;
+        link.set_peer(peer.parse::<SocketAddr>().map_err(|e| format!("{}: {}", peer, e))?);
+        Ok(link)
+    }
+
+    pub fn bind(local: &str) -> Result<UdpLink, String> {
+        let socket = UdpSocket::bind(local).map_err(|e| format!("{}: {}", local, e))?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(UdpLink {
+            socket: socket,
+            peer: None,
+
+            delay: Duration::from_millis(0),
+            loss: 0,
+            rng: Rng::new(0),
+            delayed: VecDeque::new(),
+        })
+    }
+
+    pub fn set_peer(&mut self, peer: SocketAddr) {
+        self.peer = Some(peer);
+    }
+
+    pub fn local_port(&self) -> Result<u16, String> {
+        self.socket.local_addr().map(|addr| addr.port()).map_err(|e| e.to_string())
+    }
+
+    pub fn simulate(&mut self, delay: Duration, loss: u32, seed: u64) {
+        self.delay = delay;
+        self.loss = loss;
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) {
+        if (self.rng.gen_range(100) as u32) < self.loss {
+            return;
+        }
+        self.delayed.push_back((Instant::now() + self.delay, bytes.to_vec()));
+        self.flush();
+    }
+
+    // NOTE: Sends the delayed packets that are due. A dropped packet is
+    // the same as a lost one, the inputs are sent again anyway.
+    pub fn flush(&mut self) {
+        let peer = match self.peer {
+            Some(peer) => peer,
+            None => return,
+        };
+
+        let now = Instant::now();
+        while let Some(&(due, _)) = self.delayed.front() {
+            if due > now {
+                break;
+            }
+            let (_, bytes) = self.delayed.pop_front().unwrap();
+            let _ = self.socket.send_to(&bytes, peer);
+        }
+    }
+
+    pub fn receive(&mut self) -> Vec<Vec<u8>> {
+        self.flush();
+
+        let mut packets = vec![];
+        let mut buffer = [0; 4096];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((n, from)) if Some(from) == self.peer => packets.push(buffer[..n].to_vec()),
+                Ok(_) => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        packets
+    }
+}
+
+pub struct RollbackSession {
+    local_player: usize,
+    link: UdpLink,
+
+    // NOTE: The predicted state, every frame before `frame` was played.
+    versus: Versus,
+    // NOTE: The state at the start of each frame from `confirmed_frame` on,
+    // the remote inputs of those frames are not all known yet.
+    snapshots: VecDeque<Versus>,
+    confirmed_frame: u32,
+
+    local_inputs: Vec<Vec<Input>>,
+    remote_inputs: Vec<Vec<Input>>,
+    // NOTE: How many frames of local inputs the remote has.
+    remote_ack: u32,
+
+    // NOTE: Inputs of the local player for the next `tick`.
+    pending_inputs: Vec<Input>,
+    last_packet: Instant,
+
+    rollbacks: u32,
+    // NOTE: Datagrams that were not input packets, they are skipped.
+    bad_packets: u32,
+}
+
+impl RollbackSession {
+    pub fn new(rules: &Rules, seed: u64, local_player: usize, link: UdpLink) -> RollbackSession {
+        assert!(local_player < 2);
+        RollbackSession {
+            local_player: local_player,
+            link: link,
+
+            versus: Versus::new(rules, seed),
+            snapshots: VecDeque::new(),
+            confirmed_frame: 0,
+
+            local_inputs: vec![],
+            remote_inputs: vec![],
+            remote_ack: 0,
+
+            pending_inputs: vec![],
+            last_packet: Instant::now(),
+
+            rollbacks: 0,
+            bad_packets: 0,
+        }
+    }
+
+    // NOTE: Swaps the UDP ports over a connection that just did the
+    // handshake, the host is player 1.
+    pub fn start(connection: &mut Connection,
+                 rules: &Rules,
+                 seed: u64,
+                 local_player: usize)
+                 -> Result<RollbackSession, String> {
+        let mut link = UdpLink::bind("0.0.0.0:0")?;
+        connection.send(&Message::Link {port: link.local_port()?})?;
+
+        let deadline = Instant::now() + REMOTE_TIMEOUT;
+        let port = loop {
+            connection.flush()?;
+            let port = connection.receive()?.into_iter().filter_map(|message| {
+                match message {
+                    Message::Link {port} => Some(port),
+                    _ => None,
+                }
+            }).next();
+            if let Some(port) = port {
+                break port;
+            }
+            if Instant::now() > deadline {
+                return Err("the other side didn't start a rollback game".to_string());
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        let mut peer = connection.peer_addr()?;
+        peer.set_port(port);
+        link.set_peer(peer);
+        Ok(RollbackSession::new(rules, seed, local_player, link))
+    }
+
+    pub fn local_player(&self) -> usize {
+        self.local_player
+    }
+
+    pub fn versus(&self) -> &Versus {
+        &self.versus
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.versus.frame()
+    }
+
+    pub fn confirmed_frame(&self) -> u32 {
+        self.confirmed_frame
+    }
+
+    // NOTE: How many times a misprediction made the session play frames
+    // again.
+    pub fn rollbacks(&self) -> u32 {
+        self.rollbacks
+    }
+
+    pub fn bad_packets(&self) -> u32 {
+        self.bad_packets
+    }
+
+    // NOTE: Nothing came from the remote for `REMOTE_TIMEOUT`.
+    pub fn is_remote_gone(&self) -> bool {
+        self.last_packet.elapsed() > REMOTE_TIMEOUT
+    }
+
+    // NOTE: A board ended on a frame whose inputs are all known, an end
+    // that was only predicted could still be rolled back.
+    pub fn is_ended(&self) -> bool {
+        self.snapshots.front().unwrap_or(&self.versus).is_ended()
+    }
+
+    // NOTE: False when the local player is too far ahead of the remote,
+    // wait for its inputs before calling `advance`.
+    pub fn can_advance(&self) -> bool {
+        self.frame() - self.confirmed_frame < MAX_ROLLBACK_FRAMES
+    }
+
+    fn simulate(&mut self) {
+        let frame = self.frame() as usize;
+        self.snapshots.push_back(self.versus.clone());
+
+        let local = &self.local_inputs[frame][..];
+        // NOTE: The prediction, nothing changes.
+        let remote = self.remote_inputs.get(frame).map_or(&[][..], |inputs| &inputs[..]);
+        let inputs = if self.local_player == 0 {
+            [local, remote]
+        } else {
+            [remote, local]
+        };
+        self.versus.tick(inputs);
+    }
+
+    // NOTE: Plays the next frame with the local inputs of this frame.
+    pub fn advance(&mut self, inputs: Vec<Input>) {
+        assert!(self.can_advance());
+        self.local_inputs.push(inputs);
+        self.simulate();
+        self.confirm();
+        self.send();
+    }
+
+    // NOTE: Use instead of `Playfield::handle_input` for the local player,
+    // the input is played on the next `tick`.
+    pub fn handle_input(&mut self, input: Input) {
+        self.pending_inputs.push(input);
+    }
+
+    // NOTE: Once per frame, plays the next frame unless the remote is too
+    // far behind.
+    pub fn tick(&mut self) {
+        self.poll();
+        if self.can_advance() {
+            let inputs = mem::take(&mut self.pending_inputs);
+            self.advance(inputs);
+        }
+    }
+
+    fn send(&mut self) {
+        let first_frame = self.remote_ack;
+        let packet = InputPacket {
+            ack: self.remote_inputs.len() as u32,
+            first_frame: first_frame,
+            frames: self.local_inputs[first_frame as usize..].to_vec(),
+        };
+        self.link.send(packet.format().as_bytes());
+    }
+
+    // NOTE: Drops the snapshots of the frames whose remote inputs are
+    // known, they will never be played again.
+    fn confirm(&mut self) {
+        let known = (self.remote_inputs.len() as u32).min(self.frame());
+        while self.confirmed_frame < known {
+            self.snapshots.pop_front();
+            self.confirmed_frame += 1;
+        }
+    }
+
+    // NOTE: Call once per frame, also while stalled.
+    pub fn poll(&mut self) {
+        let mut rollback_frame = None;
+
+        for bytes in self.link.receive() {
+            // NOTE: Anyone can send to the port, a bad packet must not stop
+            // the rollback of the inputs already taken from this batch.
+            let packet = match InputPacket::parse(&String::from_utf8_lossy(&bytes)) {
+                Ok(packet) => packet,
+                Err(_) => {
+                    self.bad_packets += 1;
+                    continue;
+                }
+            };
+            self.last_packet = Instant::now();
+            self.remote_ack = self.remote_ack.max(packet.ack.min(self.local_inputs.len() as u32));
+
+            for (i, inputs) in packet.frames.into_iter().enumerate() {
+                let frame = packet.first_frame + i as u32;
+                // NOTE: Frames already known, or after a gap of a lost
+                // packet, the next packet repeats them.
+                if frame != self.remote_inputs.len() as u32 {
+                    continue;
+                }
+                // NOTE: Played with a prediction of no inputs, wrong if
+                // there were some.
+                if frame < self.frame() && !inputs.is_empty() && rollback_frame.is_none() {
+                    rollback_frame = Some(frame);
+                }
+                self.remote_inputs.push(inputs);
+            }
+        }
+
+        if let Some(frame) = rollback_frame {
+            self.rollback(frame);
+        }
+        self.confirm();
+
+        // NOTE: Keep sending until the remote has every input, the last
+        // packets may be lost too.
+        if self.remote_ack < self.local_inputs.len() as u32 {
+            self.send();
+        }
+        self.link.flush();
+    }
+
+    fn rollback(&mut self, frame: u32) {
+        self.rollbacks += 1;
+
+        let current_frame = self.frame();
+        let index = (frame - self.confirmed_frame) as usize;
+        self.versus = self.snapshots[index].clone();
+        self.snapshots.truncate(index);
+
+        while self.frame() < current_frame {
+            self.simulate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NOTE: Two sessions talking over loopback, player 1 first.
+    fn pair(rules: &Rules) -> (RollbackSession, RollbackSession) {
+        let mut links = [UdpLink::bind("127.0.0.1:0").unwrap(), UdpLink::bind("127.0.0.1:0").unwrap()];
+        let ports = [links[0].local_port().unwrap(), links[1].local_port().unwrap()];
+        links[0].set_peer(SocketAddr::from(([127, 0, 0, 1], ports[1])));
+        links[1].set_peer(SocketAddr::from(([127, 0, 0, 1], ports[0])));
+        let [link0, link1] = links;
+        (RollbackSession::new(rules, 5, 0, link0), RollbackSession::new(rules, 5, 1, link1))
+    }
+
+    fn wait_until<F: FnMut() -> bool>(mut condition: F) {
+        for _ in 0..400 {
+            if condition() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("timed out");
+    }
+
+    fn inputs(frames: usize, presses: &[(usize, Action)]) -> Vec<Vec<Input>> {
+        let mut inputs = vec![vec![]; frames];
+        for &(frame, action) in presses {
+            inputs[frame].push(Input::Pressed(action));
+            inputs[frame + 1].push(Input::Released(action));
+        }
+        inputs
+    }
+
+    fn straight(rules: &Rules, inputs: [&[Vec<Input>]; 2]) -> Versus {
+        let mut versus = Versus::new(rules, 5);
+        for (first, second) in inputs[0].iter().zip(inputs[1]) {
+            versus.tick([first, second]);
+        }
+        versus
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let packet = InputPacket {
+            ack: 12,
+            first_frame: 9,
+            frames: vec![
+                vec![],
+                vec![Input::Pressed(Action::MoveLeft), Input::Released(Action::HardDrop)],
+                vec![Input::Pressed(Action::Rotate180)],
+            ],
+        };
+        assert_eq!(packet.format(), "retris-rollback 1 12 9 - p:move_left,r:hard_drop p:rotate_180");
+        assert_eq!(InputPacket::parse(&packet.format()).unwrap(), packet);
+
+        assert_eq!(InputPacket::parse("hello").unwrap_err(), "not a rollback packet");
+        assert_eq!(InputPacket::parse("retris-rollback 2 0 0").unwrap_err(), "unsupported rollback version 2, expected 1");
+        assert_eq!(InputPacket::parse("retris-rollback 1 x 0").unwrap_err(), "bad value for `ack`");
+        assert_eq!(InputPacket::parse("retris-rollback 1 0 0 p:jump").unwrap_err(), "unknown action `jump`");
+        assert_eq!(InputPacket::parse("retris-rollback 1 0 0 x:hold").unwrap_err(), "bad input `x:hold`");
+        assert_eq!(InputPacket::parse("retris-rollback 1 0 0 hold").unwrap_err(), "bad input `hold`");
+    }
+
+    #[test]
+    fn late_remote_inputs_roll_back_to_the_straight_game() {
+        let rules = Rules::new();
+        let (mut first, mut second) = pair(&rules);
+        let first_inputs = inputs(20, &[(3, Action::MoveLeft), (12, Action::HardDrop)]);
+        let second_inputs = inputs(20, &[(2, Action::RotateClockwise), (8, Action::HardDrop)]);
+
+        // NOTE: The first side plays ahead on predictions.
+        for inputs in &first_inputs {
+            first.advance(inputs.clone());
+        }
+        assert_eq!(first.confirmed_frame(), 0);
+        assert_eq!(first.rollbacks(), 0);
+
+        for inputs in &second_inputs {
+            second.poll();
+            second.advance(inputs.clone());
+        }
+        wait_until(|| {
+            first.poll();
+            second.poll();
+            first.confirmed_frame() == 20 && second.confirmed_frame() == 20
+        });
+
+        assert!(first.rollbacks() > 0);
+        let expected = straight(&rules, [&first_inputs, &second_inputs]).checksum();
+        assert_eq!(first.versus().checksum(), expected);
+        assert_eq!(second.versus().checksum(), expected);
+    }
+
+    #[test]
+    fn ended_only_once_confirmed() {
+        let mut rules = Rules::new();
+        rules.height = 4;
+        let (mut first, mut second) = pair(&rules);
+        let presses = (0..6).map(|i| (1 + 3 * i, Action::HardDrop)).collect::<Vec<_>>();
+        let first_inputs = inputs(20, &presses);
+
+        for inputs in &first_inputs {
+            first.advance(inputs.clone());
+        }
+        // NOTE: Topped out on a predicted frame, the remote could still have
+        // done something before it.
+        assert!(first.versus().playfield(0).is_lost());
+        assert!(!first.is_ended());
+
+        for _ in 0..20 {
+            second.poll();
+            second.advance(vec![]);
+        }
+        wait_until(|| {
+            first.poll();
+            second.poll();
+            first.confirmed_frame() == 20 && second.confirmed_frame() == 20
+        });
+        assert!(first.is_ended());
+        assert!(second.is_ended());
+    }
+
+    #[test]
+    fn bad_datagrams_are_skipped() {
+        let rules = Rules::new();
+        let (mut first, mut second) = pair(&rules);
+        for _ in 0..10 {
+            first.advance(vec![]);
+        }
+
+        // NOTE: In the same batch as the inputs, before and after some of
+        // them.
+        let second_inputs = inputs(10, &[(1, Action::MoveRight), (6, Action::Hold)]);
+        for (frame, inputs) in second_inputs.iter().enumerate() {
+            if frame == 5 {
+                second.link.send(b"retris-rollback 1 x");
+            }
+            second.advance(inputs.clone());
+        }
+        second.link.send(b"hello");
+
+        wait_until(|| {
+            first.poll();
+            first.bad_packets() == 2 && first.confirmed_frame() == 10
+        });
+        assert!(first.rollbacks() > 0);
+        let expected = straight(&rules, [&inputs(10, &[]), &second_inputs]).checksum();
+        assert_eq!(first.versus().checksum(), expected);
+    }
+}
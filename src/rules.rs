@@ -0,0 +1,82 @@
+use handling::Handling;
+use level::{LevelGoal, GUIDELINE_GRAVITY_TABLE};
+use randomizer::RandomizerKind;
+
+// NOTE: What finishes the game, besides topping out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Goal {
+    None,
+    Lines(u32),
+    // NOTE: Time is up after this many frames, see `Playfield::time`.
+    Time(u32),
+    // NOTE: Every garbage row is cleared.
+    Garbage,
+}
+
+// NOTE: A garbage row rises every `interval` frames, the interval gets
+// `acceleration` frames shorter with each row down to `min_interval`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RisingGarbage {
+    pub interval: u32,
+    pub min_interval: u32,
+    pub acceleration: u32,
+}
+
+// NOTE: Everything that differs between game modes. A `Playfield` is
+// created from a `Rules` and a seed.
+#[derive(Clone, Debug)]
+pub struct Rules {
+    pub width: usize,
+    pub height: usize,
+
+    pub randomizer: RandomizerKind,
+
+    pub start_level: u32,
+    pub level_goal: LevelGoal,
+    // NOTE: Gravity of each level starting from level 1, see `level::G`.
+    pub gravity_table: Vec<u32>,
+
+    pub handling: Handling,
+
+    pub goal: Goal,
+
+    // NOTE: Garbage rows the playfield starts with.
+    pub garbage_rows: usize,
+    // NOTE: Chance in percent that a garbage row has its hole somewhere
+    // else than the row below. Received garbage only changes it between
+    // attacks, the rows of one attack share a hole.
+    pub garbage_hole_change: u32,
+    pub rising_garbage: Option<RisingGarbage>,
+    // NOTE: Frames received garbage waits before it can enter the board.
+    pub garbage_delay: u32,
+}
+
+impl Rules {
+    pub fn new() -> Rules {
+        Rules {
+            width: 10,
+            height: 20,
+
+            randomizer: RandomizerKind::Bag7,
+
+            start_level: 1,
+            level_goal: LevelGoal::Fixed(10),
+            gravity_table: GUIDELINE_GRAVITY_TABLE.to_vec(),
+
+            handling: Handling::new(),
+
+            goal: Goal::None,
+
+            garbage_rows: 0,
+            garbage_hole_change: 100,
+            rising_garbage: None,
+            garbage_delay: 30,
+        }
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules::new()
+    }
+}
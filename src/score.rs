@@ -0,0 +1,201 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full,
+}
+
+// NOTE: What happened when a block locked.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LineClear {
+    pub lines: usize,
+    pub t_spin: TSpin,
+}
+
+impl LineClear {
+    // NOTE: Tetrises and T-spins that clear lines keep back-to-back alive.
+    pub fn is_difficult(&self) -> bool {
+        self.lines >= 4 || (self.lines > 0 && self.t_spin != TSpin::None)
+    }
+
+    fn base_points(&self) -> u64 {
+        match (self.t_spin, self.lines) {
+            (TSpin::None, 0) => 0,
+            (TSpin::None, 1) => 100,
+            (TSpin::None, 2) => 300,
+            (TSpin::None, 3) => 500,
+            (TSpin::None, _) => 800,
+
+            (TSpin::Mini, 0) => 100,
+            (TSpin::Mini, 1) => 200,
+            (TSpin::Mini, _) => 400,
+
+            (TSpin::Full, 0) => 400,
+            (TSpin::Full, 1) => 800,
+            (TSpin::Full, 2) => 1200,
+            (TSpin::Full, _) => 1600,
+        }
+    }
+}
+
+// NOTE: Guideline scoring, see https://tetris.wiki/Scoring.
+#[derive(Clone, Debug)]
+pub struct Score {
+    score: u64,
+    lines: u32,
+
+    // NOTE: -1 means no combo, 0 is the first clear of a chain.
+    combo: i32,
+    is_back_to_back: bool,
+
+    last_clear: Option<LineClear>,
+}
+
+impl Score {
+    pub fn new() -> Score {
+        Score {
+            score: 0,
+            lines: 0,
+
+            combo: -1,
+            is_back_to_back: false,
+
+            last_clear: None,
+        }
+    }
+
+    pub fn score(&self) -> u64 {
+        self.score
+    }
+
+    pub fn lines(&self) -> u32 {
+        self.lines
+    }
+
+    pub fn combo(&self) -> i32 {
+        self.combo
+    }
+
+    pub fn is_back_to_back(&self) -> bool {
+        self.is_back_to_back
+    }
+
+    pub fn last_clear(&self) -> Option<&LineClear> {
+        self.last_clear.as_ref()
+    }
+
+    pub fn award_soft_drop(&mut self, cells: u32) {
+        self.score += cells as u64;
+    }
+
+    pub fn award_hard_drop(&mut self, cells: u32) {
+        self.score += 2 * cells as u64;
+    }
+
+    pub fn award_lock(&mut self, clear: LineClear, level: u32) {
+        let level = level as u64;
+        let mut points = clear.base_points() * level;
+
+        if clear.lines > 0 {
+            if clear.is_difficult() {
+                if self.is_back_to_back {
+                    points += points / 2;
+                }
+                self.is_back_to_back = true;
+            } else {
+                self.is_back_to_back = false;
+            }
+
+            self.combo += 1;
+            if self.combo > 0 {
+                points += 50 * self.combo as u64 * level;
+            }
+        } else {
+            self.combo = -1;
+        }
+
+        self.score += points;
+        self.lines += clear.lines as u32;
+        self.last_clear = Some(clear);
+    }
+}
+
+impl Default for Score {
+    fn default() -> Score {
+        Score::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear(lines: usize, t_spin: TSpin) -> LineClear {
+        LineClear {
+            lines: lines,
+            t_spin: t_spin,
+        }
+    }
+
+    #[test]
+    fn line_clears_times_level() {
+        let table = [(1, 100), (2, 300), (3, 500), (4, 800)];
+        for &(lines, points) in &table {
+            let mut score = Score::new();
+            score.award_lock(clear(lines, TSpin::None), 3);
+            assert_eq!(score.score(), points * 3);
+            assert_eq!(score.lines(), lines as u32);
+        }
+    }
+
+    #[test]
+    fn t_spins() {
+        let table = [
+            (TSpin::Mini, 0, 100), (TSpin::Mini, 1, 200), (TSpin::Mini, 2, 400),
+            (TSpin::Full, 0, 400), (TSpin::Full, 1, 800), (TSpin::Full, 2, 1200), (TSpin::Full, 3, 1600),
+        ];
+        for &(t_spin, lines, points) in &table {
+            let mut score = Score::new();
+            score.award_lock(clear(lines, t_spin), 1);
+            assert_eq!(score.score(), points);
+        }
+    }
+
+    #[test]
+    fn back_to_back() {
+        let mut score = Score::new();
+        score.award_lock(clear(4, TSpin::None), 1);
+        score.award_lock(clear(0, TSpin::None), 1);
+        assert!(score.is_back_to_back());
+
+        // NOTE: 800 and half again, no combo after the empty lock.
+        score.award_lock(clear(4, TSpin::None), 1);
+        assert_eq!(score.score(), 800 + 1200);
+
+        score.award_lock(clear(1, TSpin::None), 1);
+        assert!(!score.is_back_to_back());
+    }
+
+    #[test]
+    fn combo() {
+        let mut score = Score::new();
+        score.award_lock(clear(1, TSpin::None), 2);
+        assert_eq!(score.combo(), 0);
+        assert_eq!(score.score(), 200);
+
+        score.award_lock(clear(1, TSpin::None), 2);
+        assert_eq!(score.combo(), 1);
+        assert_eq!(score.score(), 200 + 200 + 100);
+
+        score.award_lock(clear(0, TSpin::None), 2);
+        assert_eq!(score.combo(), -1);
+    }
+
+    #[test]
+    fn drops() {
+        let mut score = Score::new();
+        score.award_soft_drop(5);
+        score.award_hard_drop(10);
+        assert_eq!(score.score(), 25);
+    }
+}
@@ -0,0 +1,109 @@
+use retris::mode::{Mode, ModeOptions};
+use retris::randomizer::RANDOMIZERS;
+use retris::timer::FPS;
+
+// NOTE: Limits of the options, inclusive.
+const MIN_ULTRA_MINUTES: u32 = 1;
+const MAX_ULTRA_MINUTES: u32 = 10;
+const MIN_START_LEVEL: u32 = 1;
+const MAX_START_LEVEL: u32 = 15;
+const MIN_LEVEL_LINES: u32 = 5;
+const MAX_LEVEL_LINES: u32 = 30;
+const MARATHON_LINES_STEP: u32 = 10;
+const MAX_MARATHON_LINES: u32 = 300;
+// NOTE: Leaves room to spawn on a 20 rows playfield.
+const MIN_CHEESE_ROWS: usize = 1;
+const MAX_CHEESE_ROWS: usize = 18;
+const CHEESE_HOLE_CHANGE_STEP: u32 = 10;
+const MIN_SURVIVAL_SECONDS: u32 = 2;
+const MAX_SURVIVAL_SECONDS: u32 = 10;
+
+// NOTE: An option of a mode, changed with left and right before the game
+// starts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModeOption {
+    Randomizer,
+    UltraTime,
+    StartLevel,
+    LevelLines,
+    MarathonLines,
+    CheeseRows,
+    CheeseHoleChange,
+    SurvivalInterval,
+}
+
+pub fn mode_options(mode: Mode) -> &'static [ModeOption] {
+    match mode {
+        Mode::Ultra => &[ModeOption::UltraTime, ModeOption::Randomizer],
+        Mode::Marathon => &[ModeOption::StartLevel, ModeOption::LevelLines, ModeOption::MarathonLines,
+                            ModeOption::Randomizer],
+        Mode::Cheese => &[ModeOption::CheeseRows, ModeOption::CheeseHoleChange, ModeOption::Randomizer],
+        Mode::Survival => &[ModeOption::SurvivalInterval, ModeOption::Randomizer],
+        Mode::Sprint | Mode::Versus | Mode::Endless => &[ModeOption::Randomizer],
+    }
+}
+
+pub fn option_item(option: ModeOption, options: &ModeOptions) -> String {
+    match option {
+        ModeOption::Randomizer => format!("Randomizer {}", options.randomizer.title()),
+        ModeOption::UltraTime => format!("Time {} min", options.ultra_time / (60 * FPS)),
+        ModeOption::StartLevel => format!("Level {}", options.start_level),
+        ModeOption::LevelLines => format!("Lines per level {}", options.level_lines),
+        ModeOption::MarathonLines => {
+            match options.marathon_lines {
+                Some(lines) => format!("Goal {} lines", lines),
+                None => "Goal endless".to_string(),
+            }
+        }
+        ModeOption::CheeseRows => format!("Rows {}", options.cheese_rows),
+        ModeOption::CheeseHoleChange => format!("Hole change {}%", options.cheese_hole_change),
+        ModeOption::SurvivalInterval => format!("First rows every {}s", options.survival_interval / FPS),
+    }
+}
+
+fn step(value: u32, direction: i32, min: u32, max: u32) -> u32 {
+    (value as i32 + direction).max(min as i32).min(max as i32) as u32
+}
+
+pub fn adjust_option(option: ModeOption, options: &mut ModeOptions, direction: i32) {
+    match option {
+        ModeOption::Randomizer => {
+            let index = RANDOMIZERS.iter().position(|&kind| kind == options.randomizer).unwrap_or(0);
+            let index = step(index as u32, direction, 0, RANDOMIZERS.len() as u32 - 1);
+            options.randomizer = RANDOMIZERS[index as usize];
+        }
+        ModeOption::UltraTime => {
+            let minutes = step(options.ultra_time / (60 * FPS), direction, MIN_ULTRA_MINUTES, MAX_ULTRA_MINUTES);
+            options.ultra_time = minutes * 60 * FPS;
+        }
+        ModeOption::StartLevel => {
+            options.start_level = step(options.start_level, direction, MIN_START_LEVEL, MAX_START_LEVEL);
+        }
+        ModeOption::LevelLines => {
+            options.level_lines = step(options.level_lines, direction, MIN_LEVEL_LINES, MAX_LEVEL_LINES);
+        }
+        // NOTE: Endless comes after the longest goal.
+        ModeOption::MarathonLines => {
+            options.marathon_lines = match (options.marathon_lines, direction > 0) {
+                (Some(lines), true) if lines >= MAX_MARATHON_LINES => None,
+                (Some(lines), true) => Some(lines + MARATHON_LINES_STEP),
+                (Some(lines), false) => Some(lines.saturating_sub(MARATHON_LINES_STEP).max(MARATHON_LINES_STEP)),
+                (None, true) => None,
+                (None, false) => Some(MAX_MARATHON_LINES),
+            };
+        }
+        ModeOption::CheeseRows => {
+            options.cheese_rows = step(options.cheese_rows as u32, direction,
+                                       MIN_CHEESE_ROWS as u32, MAX_CHEESE_ROWS as u32) as usize;
+        }
+        ModeOption::CheeseHoleChange => {
+            options.cheese_hole_change = step(options.cheese_hole_change,
+                                              direction * CHEESE_HOLE_CHANGE_STEP as i32,
+                                              0, 100);
+        }
+        ModeOption::SurvivalInterval => {
+            let seconds = step(options.survival_interval / FPS, direction, MIN_SURVIVAL_SECONDS, MAX_SURVIVAL_SECONDS);
+            options.survival_interval = seconds * FPS;
+        }
+    }
+}
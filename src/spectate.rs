@@ -0,0 +1,477 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use block::{Block, BlockTemplateRef};
+use playfield::{Playfield, PlayfieldState};
+
+// NOTE: Live games for spectators, the game sends the state of
+// every playfield once per frame to everyone connected, who draws it with
+// their own renderer. After a `retris-spectate 1` line the stream is plain
+// text, one snapshot per playfield:
+//
+//   snapshot 0 1234
+//   state falling
+//   board 10 20
+//   row ..........
+//   ...
+//   row 0g.3333ggg
+//   active T 4,18 5,18 6,18 5,19
+//   ghost T 4,0 5,0 6,0 5,1
+//   hold I
+//   next S Z O
+//   stats 1200 8 1 1140 23 4
+//   end
+//
+// `snapshot` has the player and the frame. The rows go from the top down,
+// a cell is `.` when empty, `g` for garbage, or the color index of the block
+// in the blocks bitmap. The active piece and its ghost are the shape and the
+// cells on the board, x from the left and y from the bottom. `stats` are the
+// score, lines, level, time in frames, pieces and incoming garbage.
+//
+// Bump `SPECTATE_VERSION` whenever the format changes.
+pub const SPECTATE_VERSION: u32 = 1;
+
+pub const DEFAULT_SPECTATE_PORT: u16 = 7272;
+
+const MAGIC: &str = "retris-spectate";
+
+const SHAPE_NAMES: [&str; 7] = ["I", "O", "T", "S", "Z", "J", "L"];
+
+pub fn shape_name(shape: usize) -> &'static str {
+    SHAPE_NAMES[shape]
+}
+
+pub fn shape_from_name(name: &str) -> Option<usize> {
+    SHAPE_NAMES.iter().position(|&shape_name| shape_name == name)
+}
+
+fn state_name(state: &PlayfieldState) -> &'static str {
+    match *state {
+        PlayfieldState::Prepare {..} => "prepare",
+        PlayfieldState::Spawn {..} => "spawn",
+        PlayfieldState::Falling {..} => "falling",
+        PlayfieldState::Locking {..} => "locking",
+        PlayfieldState::Breaking {..} => "breaking",
+        PlayfieldState::Lost => "lost",
+        PlayfieldState::Finished => "finished",
+    }
+}
+
+// NOTE: A piece on the board.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PieceSnapshot {
+    pub shape: usize,
+    pub cells: Vec<(i32, i32)>,
+}
+
+impl PieceSnapshot {
+    fn new(template: &BlockTemplateRef, block: &Block, x: i32, y: i32) -> PieceSnapshot {
+        PieceSnapshot {
+            shape: template.shape,
+            cells: block_iter!(block).map(|(col, row, _)| (x + col as i32, y + row as i32)).collect(),
+        }
+    }
+
+    fn format(&self) -> String {
+        let cells = self.cells.iter().map(|&(x, y)| format!("{},{}", x, y)).collect::<Vec<_>>();
+        format!("{} {}", shape_name(self.shape), cells.join(" "))
+    }
+
+    fn parse(values: &[&str]) -> Result<Option<PieceSnapshot>, String> {
+        match values.first() {
+            Some(&"none") => return Ok(None),
+            None => return Err("missing piece".to_string()),
+            _ => {}
+        }
+
+        let shape = shape_from_name(values[0]).ok_or_else(|| format!("unknown shape `{}`", values[0]))?;
+        let mut cells = vec![];
+        for value in &values[1..] {
+            let mut xy = value.splitn(2, ',').map(|n| n.parse::<i32>());
+            match (xy.next(), xy.next()) {
+                (Some(Ok(x)), Some(Ok(y))) => cells.push((x, y)),
+                _ => return Err(format!("bad cell `{}`", value)),
+            }
+        }
+        Ok(Some(PieceSnapshot {
+            shape: shape,
+            cells: cells,
+        }))
+    }
+}
+
+// NOTE: What a spectator sees of one playfield on one frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub player: usize,
+    pub frame: u32,
+    pub state: String,
+
+    pub width: usize,
+    pub height: usize,
+    // NOTE: The rows from the top down, one char per cell, see above.
+    pub rows: Vec<String>,
+
+    pub active: Option<PieceSnapshot>,
+    pub ghost: Option<PieceSnapshot>,
+    pub hold: Option<usize>,
+    pub next: Vec<usize>,
+
+    pub score: u64,
+    pub lines: u32,
+    pub level: u32,
+    pub time: u32,
+    pub pieces: u32,
+    pub incoming_garbage: u32,
+}
+
+fn parse_value<T: ::std::str::FromStr>(key: &str, value: Option<&&str>) -> Result<T, String> {
+    value.and_then(|value| value.parse::<T>().ok())
+         .ok_or_else(|| format!("bad value for `{}`", key))
+}
+
+impl Snapshot {
+    pub fn new(player: usize, playfield: &Playfield) -> Snapshot {
+        let raw = playfield.raw();
+        let block = raw.block();
+
+        let rows = (0..block.height).rev().map(|row| {
+            (0..block.width).map(|col| {
+                match block.get(col, row) {
+                    Some(cell) if cell.is_garbage() => 'g',
+                    Some(cell) => ::std::char::from_digit(cell.index as u32, 36).unwrap_or('?'),
+                    None => '.',
+                }
+            }).collect()
+        }).collect();
+
+        let active = raw.falling_block().map(|falling_block| {
+            let block = raw.block_template().block(&falling_block.template);
+            PieceSnapshot::new(&falling_block.template, block, falling_block.x, falling_block.y)
+        });
+        let ghost = match (raw.falling_block(), raw.ghost_block_pos()) {
+            (Some(falling_block), Some((x, y))) => {
+                let block = raw.block_template().block(&falling_block.template);
+                Some(PieceSnapshot::new(&falling_block.template, block, x, y))
+            }
+            _ => None,
+        };
+
+        Snapshot {
+            player: player,
+            frame: playfield.frame(),
+            state: state_name(playfield.state()).to_string(),
+
+            width: block.width,
+            height: block.height,
+            rows: rows,
+
+            active: active,
+            ghost: ghost,
+            hold: raw.held_template().map(|template| template.shape),
+            next: raw.next_templates().iter().map(|template| template.shape).collect(),
+
+            score: playfield.score().score(),
+            lines: playfield.score().lines(),
+            level: playfield.level().level(),
+            time: playfield.time(),
+            pieces: playfield.pieces(),
+            incoming_garbage: playfield.garbage_queue().rows(),
+        }
+    }
+
+    pub fn format(&self) -> String {
+        let mut lines = vec![
+            format!("snapshot {} {}", self.player, self.frame),
+            format!("state {}", self.state),
+            format!("board {} {}", self.width, self.height),
+        ];
+        for row in &self.rows {
+            lines.push(format!("row {}", row));
+        }
+        let piece = |piece: &Option<PieceSnapshot>| piece.as_ref().map_or("none".to_string(), |piece| piece.format());
+        lines.push(format!("active {}", piece(&self.active)));
+        lines.push(format!("ghost {}", piece(&self.ghost)));
+        lines.push(format!("hold {}", self.hold.map_or("none", shape_name)));
+        lines.push(format!("next {}", self.next.iter().map(|&shape| shape_name(shape)).collect::<Vec<_>>().join(" ")));
+        lines.push(format!("stats {} {} {} {} {} {}",
+                           self.score, self.lines, self.level, self.time, self.pieces, self.incoming_garbage));
+        lines.push("end\n".to_string());
+        lines.join("\n")
+    }
+
+    // NOTE: The lines of one snapshot, from `snapshot` to `end`.
+    pub fn parse(lines: &[&str]) -> Result<Snapshot, String> {
+        let mut snapshot = Snapshot {
+            player: 0,
+            frame: 0,
+            state: String::new(),
+            width: 0,
+            height: 0,
+            rows: vec![],
+            active: None,
+            ghost: None,
+            hold: None,
+            next: vec![],
+            score: 0,
+            lines: 0,
+            level: 0,
+            time: 0,
+            pieces: 0,
+            incoming_garbage: 0,
+        };
+
+        for line in lines {
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            let (key, values) = match parts.split_first() {
+                Some((key, values)) => (*key, values),
+                None => continue,
+            };
+
+            match key {
+                "snapshot" => {
+                    snapshot.player = parse_value(key, values.first())?;
+                    snapshot.frame = parse_value(key, values.get(1))?;
+                }
+                "state" => snapshot.state = parse_value(key, values.first())?,
+                "board" => {
+                    snapshot.width = parse_value(key, values.first())?;
+                    snapshot.height = parse_value(key, values.get(1))?;
+                }
+                "row" => snapshot.rows.push(parse_value(key, values.first())?),
+                "active" => snapshot.active = PieceSnapshot::parse(values)?,
+                "ghost" => snapshot.ghost = PieceSnapshot::parse(values)?,
+                "hold" => {
+                    snapshot.hold = match values.first() {
+                        Some(&"none") => None,
+                        Some(name) => Some(shape_from_name(name).ok_or_else(|| format!("unknown shape `{}`", name))?),
+                        None => return Err(format!("bad value for `{}`", key)),
+                    };
+                }
+                "next" => {
+                    snapshot.next = values.iter()
+                                          .map(|name| shape_from_name(name).ok_or_else(|| format!("unknown shape `{}`", name)))
+                                          .collect::<Result<_, _>>()?;
+                }
+                "stats" => {
+                    snapshot.score = parse_value(key, values.first())?;
+                    snapshot.lines = parse_value(key, values.get(1))?;
+                    snapshot.level = parse_value(key, values.get(2))?;
+                    snapshot.time = parse_value(key, values.get(3))?;
+                    snapshot.pieces = parse_value(key, values.get(4))?;
+                    snapshot.incoming_garbage = parse_value(key, values.get(5))?;
+                }
+                "end" => {}
+                _ => return Err(format!("unknown key `{}`", key)),
+            }
+        }
+
+        if snapshot.rows.len() != snapshot.height {
+            return Err(format!("expected {} rows, got {}", snapshot.height, snapshot.rows.len()));
+        }
+        // NOTE: Spectators index the rows by the width of the board.
+        if let Some(row) = snapshot.rows.iter().find(|row| row.chars().count() != snapshot.width) {
+            return Err(format!("expected rows of {} cells, got `{}`", snapshot.width, row));
+        }
+        Ok(snapshot)
+    }
+}
+
+struct Viewer {
+    stream: TcpStream,
+    // NOTE: What is left to send, a slow viewer skips snapshots until it
+    // is sent.
+    pending: Vec<u8>,
+}
+
+impl Viewer {
+    // NOTE: Returns false once the viewer is gone.
+    fn flush(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+// NOTE: Accepts spectators and sends them the playfields, never blocks the
+// game.
+pub struct SpectatorServer {
+    listener: TcpListener,
+    viewers: Vec<Viewer>,
+}
+
+impl SpectatorServer {
+    // NOTE: `127.0.0.1:7272` for viewers on this machine only, `0.0.0.0:7272`
+    // to let anyone on the network watch.
+    pub fn bind(address: &str) -> Result<SpectatorServer, String> {
+        let listener = TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(SpectatorServer {
+            listener: listener,
+            viewers: vec![],
+        })
+    }
+
+    pub fn viewer_count(&self) -> usize {
+        self.viewers.len()
+    }
+
+    fn accept(&mut self) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() && stream.set_nodelay(true).is_ok() {
+                self.viewers.push(Viewer {
+                    stream: stream,
+                    pending: format!("{} {}\n", MAGIC, SPECTATE_VERSION).into_bytes(),
+                });
+            }
+        }
+    }
+
+    // NOTE: Call once per frame with every playfield of the game, player 1
+    // first.
+    pub fn broadcast(&mut self, playfields: &[&Playfield]) {
+        self.accept();
+        if self.viewers.is_empty() {
+            return;
+        }
+
+        let text = playfields.iter()
+                             .enumerate()
+                             .map(|(player, playfield)| Snapshot::new(player, playfield).format())
+                             .collect::<String>();
+        for viewer in &mut self.viewers {
+            if viewer.pending.is_empty() {
+                viewer.pending.extend_from_slice(text.as_bytes());
+            }
+        }
+
+        let mut i = 0;
+        while i < self.viewers.len() {
+            if self.viewers[i].flush() {
+                i += 1;
+            } else {
+                self.viewers.remove(i);
+            }
+        }
+    }
+}
+
+// NOTE: The other end, for spectators written in Rust.
+pub struct SpectatorClient {
+    stream: TcpStream,
+    buffer: String,
+    is_hello_received: bool,
+}
+
+impl SpectatorClient {
+    pub fn connect(address: &str) -> Result<SpectatorClient, String> {
+        let stream = TcpStream::connect(address).map_err(|e| format!("{}: {}", address, e))?;
+        stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(SpectatorClient {
+            stream: stream,
+            buffer: String::new(),
+            is_hello_received: false,
+        })
+    }
+
+    // NOTE: The snapshots that arrived since the last call.
+    pub fn receive(&mut self) -> Result<Vec<Snapshot>, String> {
+        let mut bytes = [0; 4096];
+        loop {
+            match self.stream.read(&mut bytes) {
+                Ok(0) => return Err("disconnected".to_string()),
+                Ok(n) => self.buffer.push_str(&String::from_utf8_lossy(&bytes[..n])),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+
+        if !self.is_hello_received {
+            let end = match self.buffer.find('\n') {
+                Some(end) => end,
+                None => return Ok(vec![]),
+            };
+            let hello = self.buffer[..end].trim().to_string();
+            if hello != format!("{} {}", MAGIC, SPECTATE_VERSION) {
+                return Err(format!("unsupported stream `{}`", hello));
+            }
+            self.buffer.drain(..end + 1);
+            self.is_hello_received = true;
+        }
+
+        let mut snapshots = vec![];
+        while let Some(end) = self.buffer.find("\nend\n") {
+            let text = self.buffer.drain(..end + "\nend\n".len()).collect::<String>();
+            snapshots.push(Snapshot::parse(&text.lines().collect::<Vec<_>>())?);
+        }
+        Ok(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use playfield::{Action, Input};
+    use rules::Rules;
+
+    // NOTE: A board with garbage, a held piece and a piece falling.
+    fn playfield() -> Playfield {
+        let rules = Rules::new();
+        let mut playfield = Playfield::new(&rules, 5);
+        playfield.receive_garbage(3);
+        while playfield.frame() < 600 {
+            let action = match playfield.frame() {
+                20 => Some(Action::Hold),
+                frame if frame % 60 == 0 => Some(Action::HardDrop),
+                frame if frame % 60 == 40 => Some(Action::MoveLeft),
+                _ => None,
+            };
+            if let Some(action) = action {
+                playfield.handle_input(Input::Pressed(action));
+                playfield.handle_input(Input::Released(action));
+            }
+            playfield.tick();
+        }
+        while playfield.raw().falling_block().is_none() {
+            playfield.tick();
+        }
+        playfield
+    }
+
+    fn parse(text: &str) -> Result<Snapshot, String> {
+        Snapshot::parse(&text.lines().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        let snapshot = Snapshot::new(1, &playfield());
+        assert!(snapshot.active.is_some() && snapshot.ghost.is_some() && snapshot.hold.is_some());
+        assert!(snapshot.rows.iter().any(|row| row.contains('g')));
+        assert!(snapshot.rows.iter().any(|row| row.chars().any(|cell| cell != '.' && cell != 'g')));
+
+        assert_eq!(parse(&snapshot.format()), Ok(snapshot));
+    }
+
+    #[test]
+    fn rows_must_fit_the_board() {
+        let text = Snapshot::new(0, &playfield()).format();
+        let rows = text.lines().filter(|line| line.starts_with("row ")).count();
+
+        let short = text.replacen("row ..........", "row .........", 1);
+        assert_eq!(parse(&short).unwrap_err(), "expected rows of 10 cells, got `.........`");
+        let long = text.replacen("row ..........", "row ...........", 1);
+        assert_eq!(parse(&long).unwrap_err(), "expected rows of 10 cells, got `...........`");
+        let missing = text.replacen("row ..........\n", "", 1);
+        assert_eq!(parse(&missing).unwrap_err(), format!("expected {} rows, got {}", rows, rows - 1));
+    }
+}
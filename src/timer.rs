@@ -0,0 +1,40 @@
+// NOTE: All durations in the rules are counted in frames, the game is
+// assumed to run at 60 frames per second.
+pub const FPS: u32 = 60;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Timer {
+    frames: u32,
+    elapsed: u32,
+}
+
+impl Timer {
+    pub fn new(frames: u32) -> Timer {
+        Timer {
+            frames: frames,
+            elapsed: 0,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if self.elapsed < self.frames {
+            self.elapsed += 1;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.elapsed = 0;
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.elapsed >= self.frames
+    }
+
+    pub fn elapsed(&self) -> u32 {
+        self.elapsed
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+}
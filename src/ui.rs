@@ -0,0 +1,159 @@
+use hammer::prelude::*;
+
+use input::MenuAction;
+
+// NOTE: A tiny 3x5 font so the screens don't need a font asset, each glyph
+// is 5 rows from top to bottom, the highest of the 3 bits is the left
+// column.
+const GLYPH_WIDTH: i32 = 3;
+const GLYPH_HEIGHT: i32 = 5;
+
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        _ => [0; 5],
+    }
+}
+
+pub fn fill_rect(renderer: &mut SoftwareRenderer, x0: i32, y0: i32, x1: i32, y1: i32, color: RGBA) {
+    for y in y0..y1 {
+        renderer.hline(y, x0, x1, color);
+    }
+}
+
+pub fn text_width(text: &str, scale: i32) -> i32 {
+    let len = text.chars().count() as i32;
+    if len == 0 {
+        0
+    } else {
+        (len * (GLYPH_WIDTH + 1) - 1) * scale
+    }
+}
+
+pub fn text_height(scale: i32) -> i32 {
+    GLYPH_HEIGHT * scale
+}
+
+// NOTE: (x, y) is the bottom left corner of the text.
+pub fn render_text(renderer: &mut SoftwareRenderer, x: i32, y: i32, text: &str, scale: i32, color: RGBA) {
+    for (i, c) in text.chars().enumerate() {
+        let x = x + i as i32 * (GLYPH_WIDTH + 1) * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            let y = y + (GLYPH_HEIGHT - 1 - row as i32) * scale;
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                    let x = x + col * scale;
+                    fill_rect(renderer, x, y, x + scale, y + scale, color);
+                }
+            }
+        }
+    }
+}
+
+pub fn render_text_centered(renderer: &mut SoftwareRenderer, center_x: i32, y: i32, text: &str, scale: i32, color: RGBA) {
+    render_text(renderer, center_x - text_width(text, scale) / 2, y, text, scale, color);
+}
+
+// NOTE: M:SS.CC, from a number of 60 FPS frames.
+pub fn format_time(frames: u32) -> String {
+    let centiseconds = frames as u64 * 100 / 60;
+    format!("{}:{:02}.{:02}",
+            centiseconds / 6000,
+            centiseconds / 100 % 60,
+            centiseconds % 100)
+}
+
+// NOTE: A vertical list of items, one of them selected.
+pub struct Menu {
+    items: Vec<String>,
+    selected: usize,
+}
+
+impl Menu {
+    pub fn new(items: &[&str]) -> Menu {
+        Menu {
+            items: items.iter().map(|item| item.to_string()).collect(),
+            selected: 0,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn set_item(&mut self, index: usize, item: &str) {
+        self.items[index] = item.to_string();
+    }
+
+    // NOTE: Returns the index of the confirmed item.
+    pub fn handle(&mut self, action: MenuAction) -> Option<usize> {
+        match action {
+            MenuAction::Up => {
+                self.selected = (self.selected + self.items.len() - 1) % self.items.len();
+                None
+            }
+            MenuAction::Down => {
+                self.selected = (self.selected + 1) % self.items.len();
+                None
+            }
+            MenuAction::Confirm => Some(self.selected),
+            _ => None,
+        }
+    }
+
+    // NOTE: (center_x, y) is the bottom center of the first item.
+    pub fn render(&self, renderer: &mut SoftwareRenderer, center_x: i32, y: i32, scale: i32) {
+        let line_height = text_height(scale) * 2;
+        for (i, item) in self.items.iter().enumerate() {
+            let y = y - i as i32 * line_height;
+            if i == self.selected {
+                render_text_centered(renderer, center_x, y, &format!("> {} <", item), scale, rgba(1.0, 1.0, 0.4, 1.0));
+            } else {
+                render_text_centered(renderer, center_x, y, item, scale, rgba(0.7, 0.7, 0.7, 1.0));
+            }
+        }
+    }
+}